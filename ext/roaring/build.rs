@@ -0,0 +1,9 @@
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Exposes the Ruby version as cfgs such as `ruby_gte_3_0`.
+    let _ = rb_sys_env::activate()?;
+    // rb-sys-env doesn't declare the cfgs it sets, so the ones used here are declared to keep
+    // rustc's unexpected_cfgs lint quiet.
+    println!("cargo::rustc-check-cfg=cfg(ruby_gte_3_0)");
+
+    Ok(())
+}
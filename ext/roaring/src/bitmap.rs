@@ -0,0 +1,293 @@
+//! The bitmaps wrapped by the Ruby bitmap classes: RoaringBitmap for Roaring::Bitmap, and
+//! RoaringTreemap for Roaring::Bitmap64 (see bitmap64.rs). MutWrapper is generic over Bitmap,
+//! so both classes share one implementation of their methods.
+
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    iter,
+    ops::{BitAndAssign, BitOrAssign, BitXorAssign, RangeInclusive, Sub, SubAssign},
+    str::FromStr,
+};
+
+use magnus::{Error, IntoValue, IntoValueFromNative, TryConvert};
+use roaring::{MultiOps, RoaringBitmap};
+
+use crate::{clickhouse, envelope};
+
+/// A bitmap of unsigned integers of `BITS` bits.
+pub(crate) trait Bitmap:
+    Clone
+    + Default
+    + PartialEq
+    + Send
+    + Sync
+    + 'static
+    + for<'a> BitOrAssign<&'a Self>
+    + for<'a> BitAndAssign<&'a Self>
+    + for<'a> SubAssign<&'a Self>
+    + for<'a> BitXorAssign<&'a Self>
+{
+    /// The integers of the bitmap, as Ruby Integers.
+    type Value: Copy
+        + Ord
+        + Default
+        + Sub<Output = Self::Value>
+        + Display
+        + FromStr
+        + Into<u64>
+        + TryFrom<u64>
+        + TryConvert
+        + IntoValue
+        + IntoValueFromNative;
+
+    /// The width of the integers, for error messages.
+    const BITS: u32;
+    /// The largest integer a bitmap can hold.
+    const MAX: Self::Value;
+    /// The largest decompressed payload of an envelope holding a serialized bitmap.
+    const MAX_LEN: u64;
+
+    fn from_values(values: Vec<Self::Value>) -> Self;
+    fn insert(&mut self, value: Self::Value) -> bool;
+    fn remove(&mut self, value: Self::Value) -> bool;
+    fn contains(&self, value: Self::Value) -> bool;
+    fn clear(&mut self);
+    fn len(&self) -> u64;
+    fn is_empty(&self) -> bool;
+    fn is_full(&self) -> bool;
+    fn min(&self) -> Option<Self::Value>;
+    fn max(&self) -> Option<Self::Value>;
+    /// Returns the number of integers less than or equal to `value`.
+    fn rank(&self, value: Self::Value) -> u64;
+    /// Returns the integer of rank `n`, counting from 0.
+    fn select(&self, n: u64) -> Option<Self::Value>;
+
+    /// Inserts a range of integers, returning the number of integers inserted. Raises RangeError
+    /// if that number can't be counted.
+    fn insert_range(&mut self, range: RangeInclusive<Self::Value>) -> Result<u64, Error>;
+    fn remove_range(&mut self, range: RangeInclusive<Self::Value>) -> u64;
+    fn contains_range(&self, range: RangeInclusive<Self::Value>) -> bool;
+    fn range_cardinality(&self, range: RangeInclusive<Self::Value>) -> u64;
+
+    fn is_disjoint(&self, other: &Self) -> bool;
+    fn is_subset(&self, other: &Self) -> bool;
+    fn is_superset(&self, other: &Self) -> bool;
+    fn union(&self, other: &Self) -> Self;
+    fn intersection(&self, other: &Self) -> Self;
+    fn difference(&self, other: &Self) -> Self;
+    fn symmetric_difference(&self, other: &Self) -> Self;
+    fn union_len(&self, other: &Self) -> u64;
+    fn intersection_len(&self, other: &Self) -> u64;
+    fn difference_len(&self, other: &Self) -> u64;
+    fn symmetric_difference_len(&self, other: &Self) -> u64;
+    fn union_all(bitmaps: Vec<&Self>) -> Self;
+    fn intersection_all(bitmaps: Vec<&Self>) -> Self;
+    fn xor_all(bitmaps: Vec<&Self>) -> Self;
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = Self::Value> + '_;
+    /// Iterates over the integers within `range`, seeking to its ends instead of scanning.
+    fn iter_in(
+        &self,
+        range: RangeInclusive<Self::Value>,
+    ) -> impl DoubleEndedIterator<Item = Self::Value> + '_;
+    /// Iterates over the runs of consecutive integers of the bitmap.
+    fn ranges(&self) -> impl Iterator<Item = RangeInclusive<Self::Value>> + '_;
+    /// Returns the smallest integer greater than or equal to `value` that isn't in the bitmap,
+    /// or None if there is none.
+    fn next_absent(&self, value: Self::Value) -> Option<Self::Value>;
+    /// Returns the largest integer less than or equal to `value` that isn't in the bitmap, or
+    /// None if there is none.
+    fn prev_absent(&self, value: Self::Value) -> Option<Self::Value>;
+
+    fn serialized_size(&self) -> usize;
+    /// Writes the bitmap in the portable serialization format.
+    fn serialize_into(&self, writer: impl Write) -> io::Result<()>;
+    /// Reads a bitmap in the portable serialization format from `bytes`.
+    fn deserialize(bytes: &[u8]) -> io::Result<Self>;
+    /// Reads a bitmap in the portable serialization format, and only its bytes, from `reader`.
+    fn deserialize_from(reader: impl Read) -> io::Result<Self>;
+    /// Writes the bitmap as a ClickHouse `groupBitmap` aggregate state.
+    fn to_clickhouse(&self) -> io::Result<Vec<u8>>;
+    /// Reads a bitmap from a ClickHouse `groupBitmap` aggregate state.
+    fn from_clickhouse(bytes: &[u8]) -> io::Result<Self>;
+}
+
+impl Bitmap for RoaringBitmap {
+    type Value = u32;
+
+    const BITS: u32 = 32;
+    const MAX: u32 = u32::MAX;
+    const MAX_LEN: u64 = envelope::MAX_LEN_32;
+
+    fn from_values(values: Vec<u32>) -> Self {
+        RoaringBitmap::from_iter(values)
+    }
+
+    fn insert(&mut self, value: u32) -> bool {
+        RoaringBitmap::insert(self, value)
+    }
+
+    fn remove(&mut self, value: u32) -> bool {
+        RoaringBitmap::remove(self, value)
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        RoaringBitmap::contains(self, value)
+    }
+
+    fn clear(&mut self) {
+        RoaringBitmap::clear(self)
+    }
+
+    fn len(&self) -> u64 {
+        RoaringBitmap::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        RoaringBitmap::is_empty(self)
+    }
+
+    fn is_full(&self) -> bool {
+        RoaringBitmap::is_full(self)
+    }
+
+    fn min(&self) -> Option<u32> {
+        RoaringBitmap::min(self)
+    }
+
+    fn max(&self) -> Option<u32> {
+        RoaringBitmap::max(self)
+    }
+
+    fn rank(&self, value: u32) -> u64 {
+        RoaringBitmap::rank(self, value)
+    }
+
+    fn select(&self, n: u64) -> Option<u32> {
+        RoaringBitmap::select(self, u32::try_from(n).ok()?)
+    }
+
+    fn insert_range(&mut self, range: RangeInclusive<u32>) -> Result<u64, Error> {
+        Ok(RoaringBitmap::insert_range(self, range))
+    }
+
+    fn remove_range(&mut self, range: RangeInclusive<u32>) -> u64 {
+        RoaringBitmap::remove_range(self, range)
+    }
+
+    fn contains_range(&self, range: RangeInclusive<u32>) -> bool {
+        RoaringBitmap::contains_range(self, range)
+    }
+
+    fn range_cardinality(&self, range: RangeInclusive<u32>) -> u64 {
+        RoaringBitmap::range_cardinality(self, range)
+    }
+
+    fn is_disjoint(&self, other: &Self) -> bool {
+        RoaringBitmap::is_disjoint(self, other)
+    }
+
+    fn is_subset(&self, other: &Self) -> bool {
+        RoaringBitmap::is_subset(self, other)
+    }
+
+    fn is_superset(&self, other: &Self) -> bool {
+        RoaringBitmap::is_superset(self, other)
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        self | other
+    }
+
+    fn intersection(&self, other: &Self) -> Self {
+        self & other
+    }
+
+    fn difference(&self, other: &Self) -> Self {
+        self - other
+    }
+
+    fn symmetric_difference(&self, other: &Self) -> Self {
+        self ^ other
+    }
+
+    fn union_len(&self, other: &Self) -> u64 {
+        RoaringBitmap::union_len(self, other)
+    }
+
+    fn intersection_len(&self, other: &Self) -> u64 {
+        RoaringBitmap::intersection_len(self, other)
+    }
+
+    fn difference_len(&self, other: &Self) -> u64 {
+        RoaringBitmap::difference_len(self, other)
+    }
+
+    fn symmetric_difference_len(&self, other: &Self) -> u64 {
+        RoaringBitmap::symmetric_difference_len(self, other)
+    }
+
+    fn union_all(bitmaps: Vec<&Self>) -> Self {
+        bitmaps.union()
+    }
+
+    fn intersection_all(bitmaps: Vec<&Self>) -> Self {
+        bitmaps.intersection()
+    }
+
+    fn xor_all(bitmaps: Vec<&Self>) -> Self {
+        bitmaps.symmetric_difference()
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = u32> + '_ {
+        RoaringBitmap::iter(self)
+    }
+
+    fn iter_in(&self, range: RangeInclusive<u32>) -> impl DoubleEndedIterator<Item = u32> + '_ {
+        self.range(range)
+    }
+
+    fn ranges(&self) -> impl Iterator<Item = RangeInclusive<u32>> + '_ {
+        let mut iter = RoaringBitmap::iter(self);
+        iter::from_fn(move || iter.next_range())
+    }
+
+    fn next_absent(&self, value: u32) -> Option<u32> {
+        match self.range(value..).next_range() {
+            Some(run) if *run.start() == value => run.end().checked_add(1),
+            _ => Some(value),
+        }
+    }
+
+    fn prev_absent(&self, value: u32) -> Option<u32> {
+        match self.range(..=value).next_range_back() {
+            Some(run) if *run.end() == value => run.start().checked_sub(1),
+            _ => Some(value),
+        }
+    }
+
+    fn serialized_size(&self) -> usize {
+        RoaringBitmap::serialized_size(self)
+    }
+
+    fn serialize_into(&self, writer: impl Write) -> io::Result<()> {
+        RoaringBitmap::serialize_into(self, writer)
+    }
+
+    fn deserialize(bytes: &[u8]) -> io::Result<Self> {
+        RoaringBitmap::deserialize_from(bytes)
+    }
+
+    fn deserialize_from(reader: impl Read) -> io::Result<Self> {
+        RoaringBitmap::deserialize_from(reader)
+    }
+
+    fn to_clickhouse(&self) -> io::Result<Vec<u8>> {
+        clickhouse::encode32(self)
+    }
+
+    fn from_clickhouse(bytes: &[u8]) -> io::Result<Self> {
+        clickhouse::decode32(bytes)
+    }
+}
@@ -0,0 +1,305 @@
+//! Roaring::Bitmap64, a bitmap of 64-bit integers backed by RoaringTreemap: a map from the upper
+//! 32 bits of its integers to a RoaringBitmap of their lower 32 bits, called a bucket here.
+//! MutWrapper implements its methods, so only what differs from RoaringBitmap lives here.
+
+use std::{
+    io::{self, Read, Write},
+    iter,
+    ops::RangeInclusive,
+};
+
+use magnus::{exception, Error};
+use roaring::{MultiOps, RoaringTreemap};
+
+use crate::{bitmap::Bitmap, clickhouse, envelope, portable};
+
+/// Inserts an inclusive range of integers, rejecting the whole of u64: its 2**64 integers
+/// can't be counted in a u64.
+fn insert_inclusive(data: &mut RoaringTreemap, range: RangeInclusive<u64>) -> Result<u64, Error> {
+    if range == (0..=u64::MAX) {
+        return Err(full_range_error());
+    }
+    Ok(data.insert_range(range))
+}
+
+fn full_range_error() -> Error {
+    Error::new(
+        exception::range_error(),
+        "a range of all 2**64 integers is too large for a 64-bit bitmap",
+    )
+}
+
+/// Iterates over the integers of a bitmap within `range`, seeking to both of its ends.
+///
+/// The back is sought before the front, and ranges missing every integer of the bitmap are
+/// caught beforehand, to steer clear of two bugs of RoaringTreemap's iterator in roaring 0.11.5:
+/// seeking the back after the front drops the bucket sought to, and seeking the front after a
+/// back seek that found no bucket forgets the back's bound.
+fn iter_in(
+    data: &RoaringTreemap,
+    range: RangeInclusive<u64>,
+) -> impl DoubleEndedIterator<Item = u64> + '_ {
+    let (start, end) = range.into_inner();
+    let overlaps =
+        data.min().is_some_and(|min| min <= end) && data.max().is_some_and(|max| start <= max);
+    overlaps
+        .then(|| {
+            let mut iter = data.iter();
+            iter.advance_back_to(end);
+            iter.advance_to(start);
+            iter
+        })
+        .into_iter()
+        .flatten()
+}
+
+/// Returns the smallest integer greater than or equal to `value` that isn't in the bitmap, or
+/// None if there is none. A run ending with its 32-bit bucket continues into the next bucket.
+fn next_absent(data: &RoaringTreemap, value: u64) -> Option<u64> {
+    let mut next = value;
+    for (high, bitmap) in data
+        .bitmaps()
+        .skip_while(|&(high, _)| high < (value >> 32) as u32)
+    {
+        if high != (next >> 32) as u32 {
+            break;
+        }
+        match bitmap.range(next as u32..).next_range() {
+            Some(run) if *run.start() == next as u32 => {
+                if *run.end() != u32::MAX {
+                    return Some((next & !0xFFFF_FFFF) | (*run.end() as u64 + 1));
+                }
+                if high == u32::MAX {
+                    return None;
+                }
+                next = (high as u64 + 1) << 32;
+            }
+            _ => return Some(next),
+        }
+    }
+
+    Some(next)
+}
+
+/// Returns the largest integer less than or equal to `value` that isn't in the bitmap, or None
+/// if there is none. A run starting with its 32-bit bucket continues into the previous bucket.
+fn prev_absent(data: &RoaringTreemap, value: u64) -> Option<u64> {
+    let mut prev = value;
+    for (high, bitmap) in data
+        .bitmaps()
+        .rev()
+        .skip_while(|&(high, _)| high > (value >> 32) as u32)
+    {
+        if high != (prev >> 32) as u32 {
+            break;
+        }
+        match bitmap.range(..=prev as u32).next_range_back() {
+            Some(run) if *run.end() == prev as u32 => {
+                if *run.start() != 0 {
+                    return Some((prev & !0xFFFF_FFFF) | (*run.start() as u64 - 1));
+                }
+                if high == 0 {
+                    return None;
+                }
+                prev = ((high as u64) << 32) - 1;
+            }
+            _ => return Some(prev),
+        }
+    }
+
+    Some(prev)
+}
+
+/// Iterates over the runs of consecutive integers of a bitmap, joining the runs that continue
+/// from one 32-bit bucket into the next.
+fn ranges(data: &RoaringTreemap) -> impl Iterator<Item = RangeInclusive<u64>> + '_ {
+    let mut runs = data
+        .bitmaps()
+        .flat_map(|(high, bitmap)| {
+            let high = (high as u64) << 32;
+            let mut iter = bitmap.iter();
+            iter::from_fn(move || iter.next_range())
+                .map(move |r| (high | *r.start() as u64)..=(high | *r.end() as u64))
+        })
+        .peekable();
+
+    iter::from_fn(move || {
+        let (start, mut end) = runs.next()?.into_inner();
+        while let Some(next) = runs.next_if(|next| end.checked_add(1) == Some(*next.start())) {
+            end = *next.end();
+        }
+        Some(start..=end)
+    })
+}
+
+impl Bitmap for RoaringTreemap {
+    type Value = u64;
+
+    const BITS: u32 = 64;
+    const MAX: u64 = u64::MAX;
+    const MAX_LEN: u64 = envelope::MAX_LEN_64;
+
+    fn from_values(values: Vec<u64>) -> Self {
+        RoaringTreemap::from_iter(values)
+    }
+
+    fn insert(&mut self, value: u64) -> bool {
+        RoaringTreemap::insert(self, value)
+    }
+
+    fn remove(&mut self, value: u64) -> bool {
+        RoaringTreemap::remove(self, value)
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        RoaringTreemap::contains(self, value)
+    }
+
+    fn clear(&mut self) {
+        RoaringTreemap::clear(self)
+    }
+
+    fn len(&self) -> u64 {
+        RoaringTreemap::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        RoaringTreemap::is_empty(self)
+    }
+
+    fn is_full(&self) -> bool {
+        RoaringTreemap::is_full(self)
+    }
+
+    fn min(&self) -> Option<u64> {
+        RoaringTreemap::min(self)
+    }
+
+    fn max(&self) -> Option<u64> {
+        RoaringTreemap::max(self)
+    }
+
+    fn rank(&self, value: u64) -> u64 {
+        RoaringTreemap::rank(self, value)
+    }
+
+    fn select(&self, n: u64) -> Option<u64> {
+        RoaringTreemap::select(self, n)
+    }
+
+    fn insert_range(&mut self, range: RangeInclusive<u64>) -> Result<u64, Error> {
+        insert_inclusive(self, range)
+    }
+
+    fn remove_range(&mut self, range: RangeInclusive<u64>) -> u64 {
+        RoaringTreemap::remove_range(self, range)
+    }
+
+    fn contains_range(&self, range: RangeInclusive<u64>) -> bool {
+        RoaringTreemap::contains_range(self, range)
+    }
+
+    fn range_cardinality(&self, range: RangeInclusive<u64>) -> u64 {
+        RoaringTreemap::range_cardinality(self, range)
+    }
+
+    fn is_disjoint(&self, other: &Self) -> bool {
+        RoaringTreemap::is_disjoint(self, other)
+    }
+
+    fn is_subset(&self, other: &Self) -> bool {
+        RoaringTreemap::is_subset(self, other)
+    }
+
+    fn is_superset(&self, other: &Self) -> bool {
+        RoaringTreemap::is_superset(self, other)
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        self | other
+    }
+
+    fn intersection(&self, other: &Self) -> Self {
+        self & other
+    }
+
+    fn difference(&self, other: &Self) -> Self {
+        self - other
+    }
+
+    fn symmetric_difference(&self, other: &Self) -> Self {
+        self ^ other
+    }
+
+    fn union_len(&self, other: &Self) -> u64 {
+        RoaringTreemap::union_len(self, other)
+    }
+
+    fn intersection_len(&self, other: &Self) -> u64 {
+        RoaringTreemap::intersection_len(self, other)
+    }
+
+    fn difference_len(&self, other: &Self) -> u64 {
+        RoaringTreemap::difference_len(self, other)
+    }
+
+    fn symmetric_difference_len(&self, other: &Self) -> u64 {
+        RoaringTreemap::symmetric_difference_len(self, other)
+    }
+
+    fn union_all(bitmaps: Vec<&Self>) -> Self {
+        bitmaps.union()
+    }
+
+    fn intersection_all(bitmaps: Vec<&Self>) -> Self {
+        bitmaps.intersection()
+    }
+
+    fn xor_all(bitmaps: Vec<&Self>) -> Self {
+        bitmaps.symmetric_difference()
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = u64> + '_ {
+        RoaringTreemap::iter(self)
+    }
+
+    fn iter_in(&self, range: RangeInclusive<u64>) -> impl DoubleEndedIterator<Item = u64> + '_ {
+        iter_in(self, range)
+    }
+
+    fn ranges(&self) -> impl Iterator<Item = RangeInclusive<u64>> + '_ {
+        ranges(self)
+    }
+
+    fn next_absent(&self, value: u64) -> Option<u64> {
+        next_absent(self, value)
+    }
+
+    fn prev_absent(&self, value: u64) -> Option<u64> {
+        prev_absent(self, value)
+    }
+
+    fn serialized_size(&self) -> usize {
+        RoaringTreemap::serialized_size(self)
+    }
+
+    fn serialize_into(&self, writer: impl Write) -> io::Result<()> {
+        RoaringTreemap::serialize_into(self, writer)
+    }
+
+    fn deserialize(bytes: &[u8]) -> io::Result<Self> {
+        portable::decode64(bytes).map(|(d, _)| d)
+    }
+
+    fn deserialize_from(reader: impl Read) -> io::Result<Self> {
+        portable::read64(reader)
+    }
+
+    fn to_clickhouse(&self) -> io::Result<Vec<u8>> {
+        clickhouse::encode64(self)
+    }
+
+    fn from_clickhouse(bytes: &[u8]) -> io::Result<Self> {
+        clickhouse::decode64(bytes)
+    }
+}
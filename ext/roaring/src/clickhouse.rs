@@ -0,0 +1,178 @@
+//! The binary format of ClickHouse's `groupBitmap` aggregate states, as produced by
+//! `groupBitmapState` and `bitmapBuild`.
+//!
+//! A state starts with a byte telling its representation apart:
+//!
+//! - 0, a small set: a varint count of at most 32 values, followed by the values as
+//!   little-endian 32 or 64-bit integers.
+//! - 1, a roaring bitmap: a varint byte length, followed by the bitmap in the portable format,
+//!   or for 64-bit values the portable format of a CRoaring Roaring64Map, which is also the
+//!   format of a RoaringTreemap.
+//!
+//! ClickHouse keeps up to 32 values in a small set, so bitmaps are written the same way.
+
+use std::io;
+
+use roaring::{RoaringBitmap, RoaringTreemap};
+
+use crate::portable;
+
+/// The most values ClickHouse keeps in a small set.
+const SMALL_SET_SIZE: u64 = 32;
+
+const SMALL: u8 = 0;
+const LARGE: u8 = 1;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn truncated() -> io::Error {
+    invalid("unexpected end of data".into())
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if bytes.len() < len {
+        return Err(truncated());
+    }
+    let (head, tail) = bytes.split_at(len);
+    *bytes = tail;
+    Ok(head)
+}
+
+fn read_varint(bytes: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take(bytes, 1)?[0];
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint is too long".into()))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// The two representations of a state, with the small set still encoded.
+enum State<'a> {
+    Small(&'a [u8]),
+    Large(&'a [u8]),
+}
+
+fn parse(mut bytes: &[u8], width: usize) -> io::Result<State<'_>> {
+    let kind = take(&mut bytes, 1)?[0];
+    let state = match kind {
+        SMALL => {
+            let count = read_varint(&mut bytes)?;
+            if count > SMALL_SET_SIZE {
+                return Err(invalid(format!(
+                    "small set of {} values is too large",
+                    count
+                )));
+            }
+            State::Small(take(&mut bytes, count as usize * width)?)
+        }
+        LARGE => {
+            let len = read_varint(&mut bytes)?;
+            let len = usize::try_from(len).map_err(|_| truncated())?;
+            State::Large(take(&mut bytes, len)?)
+        }
+        _ => return Err(invalid(format!("unknown bitmap representation {}", kind))),
+    };
+
+    if !bytes.is_empty() {
+        return Err(invalid(format!("{} trailing bytes", bytes.len())));
+    }
+    Ok(state)
+}
+
+/// Decodes a `groupBitmap` state of UInt32 values.
+pub fn decode32(bytes: &[u8]) -> io::Result<RoaringBitmap> {
+    match parse(bytes, 4)? {
+        State::Small(values) => Ok(values
+            .chunks_exact(4)
+            .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+            .collect()),
+        State::Large(data) => {
+            let (bitmap, len) = portable::decode32(data)?;
+            if len != data.len() {
+                return Err(invalid(format!("{} trailing bytes", data.len() - len)));
+            }
+            Ok(bitmap)
+        }
+    }
+}
+
+/// Decodes a `groupBitmap` state of UInt64 values.
+pub fn decode64(bytes: &[u8]) -> io::Result<RoaringTreemap> {
+    match parse(bytes, 8)? {
+        State::Small(values) => Ok(values
+            .chunks_exact(8)
+            .map(|v| {
+                let mut word = [0; 8];
+                word.copy_from_slice(v);
+                u64::from_le_bytes(word)
+            })
+            .collect()),
+        State::Large(data) => {
+            let (treemap, len) = portable::decode64(data)?;
+            if len != data.len() {
+                return Err(invalid(format!("{} trailing bytes", data.len() - len)));
+            }
+            Ok(treemap)
+        }
+    }
+}
+
+fn encode(
+    len: u64,
+    values: impl Iterator<Item = [u8; 8]>,
+    width: usize,
+    serialized_size: usize,
+    serialize: impl FnOnce(&mut Vec<u8>) -> io::Result<()>,
+) -> io::Result<Vec<u8>> {
+    if len <= SMALL_SET_SIZE {
+        let mut out = Vec::with_capacity(2 + len as usize * width);
+        out.push(SMALL);
+        write_varint(&mut out, len);
+        for value in values {
+            out.extend_from_slice(&value[..width]);
+        }
+        return Ok(out);
+    }
+
+    let mut out = Vec::with_capacity(11 + serialized_size);
+    out.push(LARGE);
+    write_varint(&mut out, serialized_size as u64);
+    serialize(&mut out)?;
+    Ok(out)
+}
+
+/// Encodes a bitmap as a `groupBitmap` state of UInt32 values.
+pub fn encode32(bitmap: &RoaringBitmap) -> io::Result<Vec<u8>> {
+    encode(
+        bitmap.len(),
+        bitmap.iter().map(|v| (v as u64).to_le_bytes()),
+        4,
+        bitmap.serialized_size(),
+        |out| bitmap.serialize_into(out),
+    )
+}
+
+/// Encodes a bitmap as a `groupBitmap` state of UInt64 values.
+pub fn encode64(treemap: &RoaringTreemap) -> io::Result<Vec<u8>> {
+    encode(
+        treemap.len(),
+        treemap.iter().map(u64::to_le_bytes),
+        8,
+        treemap.serialized_size(),
+        |out| treemap.serialize_into(out),
+    )
+}
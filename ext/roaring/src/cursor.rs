@@ -0,0 +1,24 @@
+//! The opaque cursors returned by `page`: a version byte then the big-endian integer the page
+//! ended with, encoded as unpadded URL-safe base64, so they fit in a query string as they are.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+/// The version byte of the cursor format.
+const VERSION: u8 = 1;
+
+/// Encodes the cursor of a page that ended with `last`.
+pub fn encode(last: u64) -> String {
+    let mut bytes = [0; 9];
+    bytes[0] = VERSION;
+    bytes[1..].copy_from_slice(&last.to_be_bytes());
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Decodes a cursor made by encode, or returns None if `cursor` isn't one.
+pub fn decode(cursor: &str) -> Option<u64> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    match bytes.split_first() {
+        Some((&VERSION, last)) => Some(u64::from_be_bytes(last.try_into().ok()?)),
+        _ => None,
+    }
+}
@@ -0,0 +1,338 @@
+//! A self-describing envelope around a serialized bitmap, used for compression and checksums.
+//!
+//! The envelope starts with a 24 byte header:
+//!
+//! | offset | size | field                                                    |
+//! |--------|------|----------------------------------------------------------|
+//! | 0      | 4    | magic, `ROAR`                                            |
+//! | 4      | 1    | format version, 1                                        |
+//! | 5      | 1    | codec: 0 for none, 1 for zstd, 2 for lz4                 |
+//! | 6      | 1    | flags, bit 0 set if a checksum follows the header        |
+//! | 7      | 1    | reserved (0)                                             |
+//! | 8      | 8    | length of the payload that follows, little-endian        |
+//! | 16     | 8    | length of the payload once decompressed, little-endian   |
+//!
+//! With the checksum flag, the header is followed by 12 more bytes:
+//!
+//! | offset | size | field                                                    |
+//! |--------|------|----------------------------------------------------------|
+//! | 24     | 8    | cardinality of the bitmap, little-endian                 |
+//! | 32     | 4    | CRC32C of the payload, little-endian                     |
+//!
+//! The payload is the portable serialization of the bitmap, compressed with the codec (a zstd
+//! frame or an lz4 block). Neither the 32-bit nor the 64-bit serialization can start with the
+//! magic, so plain serialized bitmaps are told apart from enveloped ones by their first bytes.
+//!
+//! The decompressed length is checked before anything is allocated, against the largest
+//! serialization of the bitmap type and, for lz4, against the most an lz4 block of the payload's
+//! length can hold. zstd payloads are decompressed as a stream into a buffer growing as it
+//! fills, and stop at the decompressed length, so a forged header can't claim more memory than
+//! the payload really decompresses to.
+
+use std::{
+    borrow::Cow,
+    error, fmt,
+    io::{self, Read},
+};
+
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+const MAGIC: [u8; 4] = *b"ROAR";
+const VERSION: u8 = 1;
+const BASE_HEADER_LEN: usize = 24;
+const CHECKSUM_LEN: usize = 12;
+pub const MAX_HEADER_LEN: usize = BASE_HEADER_LEN + CHECKSUM_LEN;
+
+/// The largest serialized 32-bit bitmap, about 513 MiB: the run container bitset and, for each
+/// of the 65536 containers, its key, cardinality, offset and 8 KiB bitmap. A run container is
+/// only written when it's smaller than the bitmap.
+pub const MAX_LEN_32: u64 = 4 + 8192 + 65536 * (4 + 4 + 8192);
+
+/// The largest serialized 64-bit bitmap an envelope is decoded into, 4 GiB.
+pub const MAX_LEN_64: u64 = 1 << 32;
+
+/// How many times larger than itself an lz4 block can decompress to: past its first bytes, each
+/// byte extends a match by at most 255 bytes.
+const LZ4_MAX_RATIO: u64 = 255;
+
+const CODEC_NONE: u8 = 0;
+const CODEC_ZSTD: u8 = 1;
+const CODEC_LZ4: u8 = 2;
+
+const FLAG_CHECKSUM: u8 = 1;
+
+/// How the payload of an envelope is compressed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Zstd(i32),
+    Lz4,
+}
+
+/// The payload or the cardinality of a checksummed envelope doesn't match its checksum.
+#[derive(Debug)]
+pub struct ChecksumMismatch(String);
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for ChecksumMismatch {}
+
+impl ChecksumMismatch {
+    /// Returns true if `e` was caused by a checksum mismatch.
+    pub fn is(e: &io::Error) -> bool {
+        e.get_ref().is_some_and(|inner| inner.is::<Self>())
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn mismatch(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, ChecksumMismatch(message))
+}
+
+/// Returns true if `bytes` start like an envelope rather than a plain serialized bitmap.
+pub fn is_enveloped(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Wraps a serialized bitmap of `len` integers into an envelope, or returns it unchanged without
+/// compression nor checksum.
+pub fn seal(
+    serialized: Vec<u8>,
+    len: u64,
+    compression: Compression,
+    checksum: bool,
+) -> io::Result<Vec<u8>> {
+    let decoded_len = serialized.len() as u64;
+    let (codec, payload) = match compression {
+        Compression::None if !checksum => return Ok(serialized),
+        Compression::None => (CODEC_NONE, serialized),
+        Compression::Zstd(level) => (CODEC_ZSTD, zstd::bulk::compress(&serialized, level)?),
+        Compression::Lz4 => (CODEC_LZ4, lz4_flex::compress(&serialized)),
+    };
+    let flags = if checksum { FLAG_CHECKSUM } else { 0 };
+
+    let mut sealed = Vec::with_capacity(MAX_HEADER_LEN + payload.len());
+    sealed.extend_from_slice(&MAGIC);
+    sealed.extend_from_slice(&[VERSION, codec, flags, 0]);
+    sealed.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    sealed.extend_from_slice(&decoded_len.to_le_bytes());
+    if checksum {
+        sealed.extend_from_slice(&len.to_le_bytes());
+        sealed.extend_from_slice(&crc32c::crc32c(&payload).to_le_bytes());
+    }
+    sealed.extend_from_slice(&payload);
+    Ok(sealed)
+}
+
+/// The cardinality and payload CRC of a checksummed envelope.
+struct Checksum {
+    len: u64,
+    crc: u32,
+}
+
+/// The fields of an envelope header.
+pub struct Header {
+    codec: u8,
+    payload_len: u64,
+    decoded_len: u64,
+    checksum: Option<Checksum>,
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
+
+impl Header {
+    /// Returns the length of the header starting with `bytes`, which must hold at least the
+    /// first 24 bytes of it.
+    pub fn len_of(bytes: &[u8]) -> usize {
+        if bytes.get(6).is_some_and(|flags| flags & FLAG_CHECKSUM != 0) {
+            MAX_HEADER_LEN
+        } else {
+            BASE_HEADER_LEN
+        }
+    }
+
+    /// Parses the header at the start of an envelope holding a serialized bitmap of at most
+    /// `max_len` bytes.
+    pub fn parse(bytes: &[u8], max_len: u64) -> io::Result<Self> {
+        if bytes.len() < BASE_HEADER_LEN || bytes.len() < Self::len_of(bytes) {
+            return Err(invalid("truncated envelope header".into()));
+        }
+        if !is_enveloped(bytes) {
+            return Err(invalid("missing envelope magic".into()));
+        }
+        if bytes[4] != VERSION {
+            return Err(invalid(format!(
+                "unsupported envelope version {}",
+                bytes[4]
+            )));
+        }
+        let codec = bytes[5];
+        if !matches!(codec, CODEC_NONE | CODEC_ZSTD | CODEC_LZ4) {
+            return Err(invalid(format!("unsupported compression codec {}", codec)));
+        }
+        let flags = bytes[6];
+        if flags & !FLAG_CHECKSUM != 0 || bytes[7] != 0 {
+            return Err(invalid("unsupported envelope flags".into()));
+        }
+
+        let (payload_len, decoded_len) = (read_u64(bytes, 8), read_u64(bytes, 16));
+        if decoded_len > max_len {
+            return Err(invalid(format!(
+                "envelope payload decompresses to {} bytes, more than the maximum of {}",
+                decoded_len, max_len
+            )));
+        }
+        if codec == CODEC_LZ4 && decoded_len > payload_len.saturating_mul(LZ4_MAX_RATIO) {
+            return Err(invalid(format!(
+                "an lz4 payload of {} bytes can't decompress to {} bytes",
+                payload_len, decoded_len
+            )));
+        }
+        if codec == CODEC_NONE && payload_len != decoded_len {
+            return Err(invalid(format!(
+                "uncompressed envelope payload is {} bytes, expected {}",
+                payload_len, decoded_len
+            )));
+        }
+
+        let checksum = (flags & FLAG_CHECKSUM != 0).then(|| Checksum {
+            len: read_u64(bytes, 24),
+            crc: u32::from_le_bytes([bytes[32], bytes[33], bytes[34], bytes[35]]),
+        });
+        Ok(Self {
+            codec,
+            payload_len,
+            decoded_len,
+            checksum,
+        })
+    }
+
+    /// The length of the header, including the checksum.
+    pub fn header_len(&self) -> usize {
+        if self.checksum.is_some() {
+            MAX_HEADER_LEN
+        } else {
+            BASE_HEADER_LEN
+        }
+    }
+
+    /// The number of payload bytes following the header.
+    pub fn payload_len(&self) -> u64 {
+        self.payload_len
+    }
+
+    /// Verifies the checksum of the payload and decompresses it into the plain serialized bitmap.
+    pub fn decode<'a>(&self, payload: &'a [u8]) -> io::Result<Opened<'a>> {
+        if payload.len() as u64 != self.payload_len {
+            return Err(invalid(format!(
+                "envelope payload is {} bytes, expected {}",
+                payload.len(),
+                self.payload_len
+            )));
+        }
+        if let Some(checksum) = &self.checksum {
+            let crc = crc32c::crc32c(payload);
+            if crc != checksum.crc {
+                return Err(mismatch(format!(
+                    "checksum mismatch: computed {:#010x}, expected {:#010x}",
+                    crc, checksum.crc
+                )));
+            }
+        }
+
+        // Bounded by the maximum length of the bitmap type when the header was parsed, and by the
+        // payload length for lz4, which allocates the whole buffer up front.
+        let decoded_len = self.decoded_len as usize;
+        let data = match self.codec {
+            CODEC_ZSTD => {
+                // One byte past the expected length is enough to tell that the payload is longer.
+                let mut data = Vec::new();
+                ZstdDecoder::with_buffer(payload)?
+                    .take(self.decoded_len + 1)
+                    .read_to_end(&mut data)?;
+                Cow::Owned(data)
+            }
+            CODEC_LZ4 => Cow::Owned(
+                lz4_flex::decompress(payload, decoded_len).map_err(|e| invalid(e.to_string()))?,
+            ),
+            _ => Cow::Borrowed(payload),
+        };
+        if data.len() != decoded_len {
+            return Err(invalid(format!(
+                "envelope payload decompressed to {} bytes, expected {}",
+                data.len(),
+                decoded_len
+            )));
+        }
+        Ok(Opened {
+            data,
+            len: self.checksum.as_ref().map(|checksum| checksum.len),
+        })
+    }
+}
+
+/// The plain serialized bitmap held by an envelope.
+pub struct Opened<'a> {
+    pub data: Cow<'a, [u8]>,
+    /// The cardinality recorded by a checksummed envelope.
+    len: Option<u64>,
+}
+
+impl Opened<'_> {
+    /// Checks the cardinality of the decoded bitmap against the one recorded with the checksum.
+    pub fn verify_len(&self, len: u64) -> io::Result<()> {
+        match self.len {
+            Some(expected) if expected != len => Err(mismatch(format!(
+                "cardinality mismatch: decoded {}, expected {}",
+                len, expected
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the plain serialized bitmap of at most `max_len` bytes held by `bytes`, checking and
+/// decompressing it if it is enveloped.
+pub fn open(bytes: &[u8], max_len: u64) -> io::Result<Opened<'_>> {
+    if !is_enveloped(bytes) {
+        return Ok(Opened {
+            data: Cow::Borrowed(bytes),
+            len: None,
+        });
+    }
+
+    let header = Header::parse(bytes, max_len)?;
+    header.decode(&bytes[header.header_len()..])
+}
+
+/// Reads the rest of an envelope whose first `prefix.len()` bytes were already read, and
+/// returns the plain serialized bitmap of at most `max_len` bytes.
+pub fn read(prefix: &[u8], mut reader: impl Read, max_len: u64) -> io::Result<Opened<'static>> {
+    let mut header = [0; MAX_HEADER_LEN];
+    header[..prefix.len()].copy_from_slice(prefix);
+    reader.read_exact(&mut header[prefix.len()..BASE_HEADER_LEN])?;
+    let len = Header::len_of(&header);
+    reader.read_exact(&mut header[BASE_HEADER_LEN..len])?;
+    let header = Header::parse(&header[..len], max_len)?;
+
+    let mut payload = Vec::new();
+    reader
+        .take(header.payload_len())
+        .read_to_end(&mut payload)?;
+    let opened = header.decode(&payload)?;
+    Ok(Opened {
+        data: Cow::Owned(opened.data.into_owned()),
+        len: opened.len,
+    })
+}
@@ -0,0 +1,86 @@
+use std::io;
+
+use magnus::{class, exception, memoize, prelude::*, Error, ExceptionClass, RModule};
+
+use crate::{envelope::ChecksumMismatch, interval::ParseError};
+
+fn roaring_module() -> RModule {
+    *memoize!(RModule: class::object().const_get("Roaring").unwrap())
+}
+
+fn lookup(name: &'static str) -> ExceptionClass {
+    roaring_module().const_get(name).unwrap()
+}
+
+/// Roaring::Error, the base class of every error raised by the extension.
+pub fn error() -> ExceptionClass {
+    *memoize!(ExceptionClass: lookup("Error"))
+}
+
+/// Roaring::DeserializationError, raised when binary data can't be decoded into a bitmap.
+pub fn deserialization_error() -> ExceptionClass {
+    *memoize!(ExceptionClass: lookup("DeserializationError"))
+}
+
+/// Roaring::ChecksumError, raised when checksummed data doesn't match its checksum.
+pub fn checksum_error() -> ExceptionClass {
+    *memoize!(ExceptionClass: lookup("ChecksumError"))
+}
+
+/// Converts an error decoding a serialized bitmap into a Roaring::ChecksumError or a
+/// Roaring::DeserializationError.
+pub fn invalid_data(e: &io::Error) -> Error {
+    if ChecksumMismatch::is(e) {
+        Error::new(checksum_error(), e.to_string())
+    } else {
+        Error::new(
+            deserialization_error(),
+            format!("invalid bitmap data: {}", e),
+        )
+    }
+}
+
+/// Roaring::ParseError, raised when an interval string is malformed.
+pub fn parse_error() -> ExceptionClass {
+    *memoize!(ExceptionClass: lookup("ParseError"))
+}
+
+/// Converts an interval string parse error into a Roaring::ParseError, whose `offset` is the
+/// byte offset of the problem.
+pub fn parse_failed(e: ParseError) -> Error {
+    let exception = match parse_error().new_instance((e.to_string(),)) {
+        Ok(exception) => exception,
+        Err(error) => return error,
+    };
+    if let Err(error) = exception.ivar_set("@offset", e.offset) {
+        return error;
+    }
+    exception.into()
+}
+
+/// Roaring::SerializationError, raised when a bitmap can't be encoded.
+pub fn serialization_error() -> ExceptionClass {
+    *memoize!(ExceptionClass: lookup("SerializationError"))
+}
+
+/// Roaring::ConcurrentModificationError, raised when a bitmap is modified while being iterated.
+pub fn concurrent_modification_error() -> ExceptionClass {
+    *memoize!(ExceptionClass: lookup("ConcurrentModificationError"))
+}
+
+/// Roaring::InvalidValueError, raised when a value can't be stored in a bitmap.
+pub fn invalid_value_error() -> ExceptionClass {
+    *memoize!(ExceptionClass: lookup("InvalidValueError"))
+}
+
+pub fn init(module: RModule) -> Result<(), Error> {
+    let error = module.define_error("Error", exception::standard_error())?;
+    let deserialization_error = module.define_error("DeserializationError", error)?;
+    module.define_error("ChecksumError", deserialization_error)?;
+    module.define_error("SerializationError", error)?;
+    module.define_error("ParseError", error)?;
+    module.define_error("InvalidValueError", error)?;
+    module.define_error("ConcurrentModificationError", error)?;
+
+    Ok(())
+}
@@ -0,0 +1,383 @@
+use std::{fs::File, path::PathBuf};
+
+use magnus::{
+    block::*,
+    exception, gc, method,
+    prelude::*,
+    scan_args::{get_kwargs, scan_args},
+    typed_data::Obj,
+    DataTypeFunctions, Error, RClass, RHash, RModule, RString, TryConvert, TypedData, Value,
+};
+use memmap2::Mmap;
+use roaring::RoaringBitmap;
+
+use crate::{
+    enumerator_with_size, envelope, errors, gvl, view::View, wrap_as, yield_all, MutWrapper,
+};
+
+/// The bytes a FrozenBitmap reads from.
+enum Source {
+    /// A frozen String, kept alive and pinned by the mark function.
+    String(RString),
+    /// A read-only, shared mapping of a file.
+    Mmap(Mmap),
+}
+
+/// @yard
+/// Roaring::FrozenBitmap is a read-only bitmap backed by a bitmap in the portable serialization
+/// format, as produced by Roaring::Bitmap#serialize, held in a frozen String or a memory-mapped
+/// file.
+///
+/// Only the container headers are read up front: every query reads the containers it needs
+/// straight from the serialized bytes, so opening a large bitmap is cheap and doesn't copy it.
+/// A String is kept alive (and pinned in place) for as long as the view is.
+///
+/// Opening a view checks that the headers are well formed and that every container lies within
+/// the data, but not what the containers hold, which takes reading all of them: pass
+/// `verify: true` for that check when the data comes from an untrusted source. A query over a
+/// corrupt container returns a wrong answer rather than raising.
+///
+/// Views never change, so `Ractor.make_shareable(view)` lets any Ractor query them; the String
+/// they read from is made shareable along with them.
+///
+/// Set operations materialize the result into a regular Roaring::Bitmap.
+#[derive(TypedData)]
+#[magnus(
+    class = "Roaring::FrozenBitmap",
+    free_immediately,
+    mark,
+    frozen_shareable
+)]
+pub(crate) struct FrozenBitmap {
+    source: Source,
+    view: View,
+}
+
+impl DataTypeFunctions for FrozenBitmap {
+    fn mark(&self) {
+        // rb_gc_mark pins the String, so compaction can't move its bytes from under the view.
+        if let Source::String(rstr) = self.source {
+            gc::mark(rstr);
+        }
+    }
+}
+
+/// Parses the source and the `verify:` option of `view` and `mmap`.
+fn view_args<T: TryConvert>(args: &[Value]) -> Result<(T, bool), Error> {
+    let args = scan_args::<(T,), (), (), (), RHash, ()>(args)?;
+    let kwargs = get_kwargs::<_, (), (Option<bool>,), ()>(args.keywords, &[], &["verify"])?;
+    Ok((args.required.0, kwargs.optional.0.unwrap_or(false)))
+}
+
+impl FrozenBitmap {
+    /// The serialized bitmap. A source String is frozen and pinned, and a mapped file is
+    /// expected not to change, so the bytes stay valid for as long as `self` is alive.
+    fn bytes(&self) -> &[u8] {
+        match &self.source {
+            Source::String(rstr) => unsafe { rstr.as_slice() },
+            Source::Mmap(map) => map,
+        }
+    }
+
+    fn parse(bytes: &[u8], verify: bool) -> Result<View, Error> {
+        if envelope::is_enveloped(bytes) {
+            return Err(Error::new(
+                errors::deserialization_error(),
+                "compressed or checksummed bitmaps can't be viewed in place, use Roaring::Bitmap.deserialize",
+            ));
+        }
+
+        let invalid = |e| {
+            Error::new(
+                errors::deserialization_error(),
+                format!("invalid bitmap data: {}", e),
+            )
+        };
+        let view = View::parse(bytes).map_err(invalid)?;
+        if verify {
+            gvl::without_gvl_if_large(view.serialized_size() as u64, || view.verify(bytes))
+                .map_err(invalid)?;
+        }
+        Ok(view)
+    }
+
+    /// Decodes the whole bitmap, container by container, checking them as it goes.
+    fn materialize(&self) -> Result<RoaringBitmap, Error> {
+        let bytes = &self.bytes()[..self.view.serialized_size()];
+        gvl::without_gvl_if_large(self.view.len(), || RoaringBitmap::deserialize_from(bytes))
+            .map_err(|e| {
+                Error::new(
+                    errors::deserialization_error(),
+                    format!("invalid bitmap data: {}", e),
+                )
+            })
+    }
+
+    /// Combines the bitmap with a Roaring::Bitmap or Roaring::FrozenBitmap into a new
+    /// Roaring::Bitmap. Only views are decoded: a Roaring::Bitmap is combined as it is.
+    fn combine(
+        &self,
+        other: Value,
+        op: impl FnOnce(RoaringBitmap, &RoaringBitmap) -> RoaringBitmap,
+    ) -> Result<Obj<MutWrapper<RoaringBitmap>>, Error> {
+        let lhs = self.materialize()?;
+        let result = if let Ok(other) = Obj::<Self>::try_convert(other) {
+            let rhs = other.get().materialize()?;
+            gvl::without_gvl_if_large(lhs.len() + rhs.len(), || op(lhs, &rhs))
+        } else {
+            let other = Obj::<MutWrapper<RoaringBitmap>>::try_convert(other)?;
+            let rhs = other.get().0.read()?;
+            gvl::without_gvl_if_large(lhs.len() + rhs._data.len(), || op(lhs, &rhs._data))
+        };
+
+        Ok(MutWrapper::wrap_as(
+            result,
+            <MutWrapper<RoaringBitmap>>::class(),
+        ))
+    }
+
+    /// @yard
+    /// @def view(string, verify: false)
+    ///
+    /// Creates a read-only bitmap over a frozen String holding a serialized bitmap, without
+    /// copying or decoding it.
+    ///
+    /// @param string [String] A frozen String in the portable serialization format.
+    /// @param verify [Boolean] Checks the contents of every container too, reading all of them.
+    ///
+    /// @example Viewing a serialized bitmap.
+    ///     data = Roaring::Bitmap.from_a([1, 2, 3]).serialize.freeze
+    ///     frozen = Roaring::FrozenBitmap.view(data)
+    ///     frozen.contains(2)  #=> true
+    ///     frozen.cardinality  #=> 3
+    ///
+    /// @return [Roaring::FrozenBitmap] The view.
+    /// @raise [ArgumentError] If the String is not frozen.
+    /// @raise [Roaring::DeserializationError] If the String is not a valid serialized bitmap.
+    fn view(class: RClass, args: &[Value]) -> Result<Obj<Self>, Error> {
+        let (source, verify) = view_args::<RString>(args)?;
+        if !source.is_frozen() {
+            return Err(Error::new(
+                exception::arg_error(),
+                "can't view a mutable String, freeze it first",
+            ));
+        }
+
+        let view = Self::parse(unsafe { source.as_slice() }, verify)?;
+        Ok(wrap_as(
+            Self {
+                source: Source::String(source),
+                view,
+            },
+            class,
+        ))
+    }
+
+    /// @yard
+    /// @def mmap(path, verify: false)
+    ///
+    /// Maps a file holding a serialized bitmap into memory, read-only, and queries it in place.
+    /// The file's pages live in the OS page cache, so processes mapping the same file (such as
+    /// forked workers) share a single copy of the bitmap, and opening it only reads the
+    /// container headers.
+    ///
+    /// The file must not be modified while it is mapped: queries would see the new bytes, which
+    /// may not be a valid bitmap anymore. Truncating it is undefined behaviour, and typically
+    /// kills the process with SIGBUS on the next query reading past the new end of the file.
+    /// Replace the file by renaming a new one over it instead, and map the new file.
+    ///
+    /// @param path [String, Pathname] The path of a file written from Roaring::Bitmap#serialize.
+    /// @param verify [Boolean] Checks the contents of every container too, reading the whole file.
+    ///
+    /// @example Mapping a bitmap file.
+    ///     File.binwrite("audience.bin", Roaring::Bitmap.from_a([1, 2, 3]).serialize)
+    ///     rb = Roaring::FrozenBitmap.mmap("audience.bin")
+    ///     rb.contains(2)      #=> true
+    ///
+    /// @return [Roaring::FrozenBitmap] A read-only bitmap over the file.
+    /// @raise [IOError] If the file can't be opened or mapped.
+    /// @raise [Roaring::DeserializationError] If the file is not a valid serialized bitmap.
+    pub(crate) fn mmap(class: RClass, args: &[Value]) -> Result<Obj<Self>, Error> {
+        let (path, verify) = view_args::<PathBuf>(args)?;
+        let io_error = |e: std::io::Error| {
+            Error::new(exception::io_error(), format!("{}: {}", path.display(), e))
+        };
+        let file = File::open(&path).map_err(io_error)?;
+        let map = unsafe { Mmap::map(&file) }.map_err(io_error)?;
+        let view = Self::parse(&map, verify)?;
+        Ok(wrap_as(
+            Self {
+                source: Source::Mmap(map),
+                view,
+            },
+            class,
+        ))
+    }
+
+    /// @yard
+    /// @def contains(item)
+    ///
+    /// Checks if the bitmap contains an item.
+    ///
+    /// @param [Integer] item The item to check for.
+    ///
+    /// @return [Boolean] true if the item is present, false otherwise.
+    fn contains(&self, item: u32) -> Result<bool, Error> {
+        Ok(self.view.contains(self.bytes(), item))
+    }
+
+    /// @yard
+    /// @def cardinality
+    ///
+    /// Returns the number of items in the bitmap, read from the container headers.
+    ///
+    /// @return [Integer] The number of items in the bitmap.
+    fn len(&self) -> Result<u64, Error> {
+        Ok(self.view.len())
+    }
+
+    /// @yard
+    /// @def empty?
+    ///
+    /// Checks if the bitmap is empty.
+    ///
+    /// @return [Boolean] true if the bitmap is empty, false otherwise.
+    fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.view.len() == 0)
+    }
+
+    /// @yard
+    /// @def rank(item)
+    ///
+    /// Returns the number of integers that are <= value.
+    ///
+    /// @return [Integer] The number of integers that are <= value.
+    fn rank(&self, item: u32) -> Result<u64, Error> {
+        Ok(self.view.rank(self.bytes(), item))
+    }
+
+    /// @yard
+    /// @def min
+    ///
+    /// Returns the minimum value in the bitmap.
+    ///
+    /// @return [Integer, nil] The minimum value in the bitmap, or nil if it is empty.
+    fn min(&self) -> Result<Option<u32>, Error> {
+        Ok(self.view.min(self.bytes()))
+    }
+
+    /// @yard
+    /// @def max
+    ///
+    /// Returns the maximum value in the bitmap.
+    ///
+    /// @return [Integer, nil] The maximum value in the bitmap, or nil if it is empty.
+    fn max(&self) -> Result<Option<u32>, Error> {
+        Ok(self.view.max(self.bytes()))
+    }
+
+    /// @yard
+    ///
+    /// @overload each(&block)
+    ///
+    ///     Iterates over the bitmap and yields each item, decoding one container at a time.
+    ///
+    ///     @yield [Integer] The item in the bitmap.
+    ///
+    ///     @return [Roaring::FrozenBitmap] The bitmap.
+    ///
+    /// @overload each
+    ///
+    ///     Returns an enumerator if no block is given, whose `size` is the cardinality of the
+    ///     bitmap.
+    ///
+    ///     @return [Enumerator] An enumerator.
+    fn each(rb_self: Obj<Self>) -> Result<Value, Error> {
+        if block_given() {
+            let self_struct = rb_self.get();
+            yield_all(self_struct.view.iter(self_struct.bytes()))?;
+
+            Ok(*rb_self)
+        } else {
+            Ok(enumerator_with_size(*rb_self, "each"))
+        }
+    }
+
+    /// @yard
+    /// @def to_bitmap
+    ///
+    /// Decodes the bitmap into a new, mutable Roaring::Bitmap.
+    ///
+    /// @return [Roaring::Bitmap] A copy of the bitmap.
+    fn to_bitmap(&self) -> Result<Obj<MutWrapper<RoaringBitmap>>, Error> {
+        Ok(MutWrapper::wrap_as(
+            self.materialize()?,
+            <MutWrapper<RoaringBitmap>>::class(),
+        ))
+    }
+
+    /// @yard
+    /// @def union(other)
+    ///
+    /// Unions the bitmap with a Roaring::Bitmap or Roaring::FrozenBitmap. Bitwise OR.
+    ///
+    /// @return [Roaring::Bitmap] The union of the two bitmaps.
+    fn union(&self, other: Value) -> Result<Obj<MutWrapper<RoaringBitmap>>, Error> {
+        self.combine(other, |lhs, rhs| lhs | rhs)
+    }
+
+    /// @yard
+    /// @def intersection(other)
+    ///
+    /// Intersects the bitmap with a Roaring::Bitmap or Roaring::FrozenBitmap. Bitwise AND.
+    ///
+    /// @return [Roaring::Bitmap] The intersection of the two bitmaps.
+    fn intersection(&self, other: Value) -> Result<Obj<MutWrapper<RoaringBitmap>>, Error> {
+        self.combine(other, |lhs, rhs| lhs & rhs)
+    }
+
+    /// @yard
+    /// @def difference(other)
+    ///
+    /// Computes the difference with a Roaring::Bitmap or Roaring::FrozenBitmap. Bitwise AND NOT.
+    ///
+    /// @return [Roaring::Bitmap] The difference of the two bitmaps.
+    fn difference(&self, other: Value) -> Result<Obj<MutWrapper<RoaringBitmap>>, Error> {
+        self.combine(other, |lhs, rhs| lhs - rhs)
+    }
+
+    /// @yard
+    /// @def symmetric_difference(other)
+    ///
+    /// Computes the symmetric difference with a Roaring::Bitmap or Roaring::FrozenBitmap. Bitwise XOR.
+    ///
+    /// @return [Roaring::Bitmap] The symmetric difference of the two bitmaps.
+    fn symmetric_difference(&self, other: Value) -> Result<Obj<MutWrapper<RoaringBitmap>>, Error> {
+        self.combine(other, |lhs, rhs| lhs ^ rhs)
+    }
+}
+
+pub fn init(module: RModule) -> Result<(), Error> {
+    let class = module.define_class("FrozenBitmap", Default::default())?;
+    class.define_singleton_method("view", method!(FrozenBitmap::view, -1))?;
+    class.define_singleton_method("mmap", method!(FrozenBitmap::mmap, -1))?;
+
+    class.define_method("contains", method!(FrozenBitmap::contains, 1))?;
+    class.define_method("cardinality", method!(FrozenBitmap::len, 0))?;
+    class.define_method("empty?", method!(FrozenBitmap::is_empty, 0))?;
+    class.define_method("rank", method!(FrozenBitmap::rank, 1))?;
+    class.define_method("min", method!(FrozenBitmap::min, 0))?;
+    class.define_method("max", method!(FrozenBitmap::max, 0))?;
+    class.define_method("each", method!(FrozenBitmap::each, 0))?;
+    class.define_method("to_bitmap", method!(FrozenBitmap::to_bitmap, 0))?;
+
+    class.define_method("union", method!(FrozenBitmap::union, 1))?;
+    class.define_method("intersection", method!(FrozenBitmap::intersection, 1))?;
+    class.define_method("difference", method!(FrozenBitmap::difference, 1))?;
+    class.define_method(
+        "symmetric_difference",
+        method!(FrozenBitmap::symmetric_difference, 1),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use std::{
+    ffi::c_void,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::{self, Thread},
+    time::Duration,
+};
+
+use magnus::Error;
+use rb_sys::{rb_thread_call_without_gvl2, rb_thread_check_ints, Qnil, VALUE};
+
+/// Below this many items, releasing and re-acquiring the GVL costs more than the work itself.
+const THRESHOLD: u64 = 1 << 16;
+
+/// Bounds on how long a wait sleeps between two polls.
+const MIN_POLL: Duration = Duration::from_micros(10);
+const MAX_POLL: Duration = Duration::from_millis(1);
+
+struct Call<F, R> {
+    func: Option<F>,
+    result: Option<std::thread::Result<R>>,
+}
+
+unsafe extern "C" fn trampoline<F, R>(data: *mut c_void) -> *mut c_void
+where
+    F: FnOnce() -> R,
+{
+    let call = &mut *(data as *mut Call<F, R>);
+    if let Some(func) = call.func.take() {
+        call.result = Some(panic::catch_unwind(AssertUnwindSafe(func)));
+    }
+    ptr::null_mut()
+}
+
+/// Runs `func` with the GVL released through rb_thread_call_without_gvl2, which unlike
+/// rb_thread_call_without_gvl never raises: a raise would unwind past this frame without
+/// dropping what `func` returned. Gives `func` back if Ruby didn't call it because an interrupt
+/// was already pending.
+fn call_without_gvl<F, R>(
+    func: F,
+    ubf: Option<unsafe extern "C" fn(*mut c_void)>,
+    ubf_data: *mut c_void,
+) -> Result<R, F>
+where
+    F: FnOnce() -> R,
+{
+    let mut call = Call {
+        func: Some(func),
+        result: None,
+    };
+
+    unsafe {
+        rb_thread_call_without_gvl2(
+            Some(trampoline::<F, R>),
+            &mut call as *mut Call<F, R> as *mut c_void,
+            ubf,
+            ubf_data,
+        );
+    }
+
+    match call.result {
+        Some(Ok(result)) => Ok(result),
+        Some(Err(payload)) => panic::resume_unwind(payload),
+        None => Err(call
+            .func
+            .take()
+            .expect("the function was neither called nor kept")),
+    }
+}
+
+/// Runs `func` on the current thread with the GVL released, so other Ruby threads can run
+/// in the meantime. `func` must not touch any Ruby object or call into the Ruby API.
+///
+/// The call can't be interrupted: Thread#raise and Thread#kill take effect once it returns.
+pub fn without_gvl<F, R>(func: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    call_without_gvl(func, None, ptr::null_mut()).unwrap_or_else(|func| func())
+}
+
+/// Runs `func` without the GVL when `items` is large enough to be worth it, and in place
+/// otherwise.
+pub fn without_gvl_if_large<F, R>(items: u64, func: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    if items >= THRESHOLD {
+        without_gvl(func)
+    } else {
+        func()
+    }
+}
+
+/// A thread polling without the GVL, and whether Ruby asked it to stop.
+struct Waiter {
+    thread: Thread,
+    interrupted: AtomicBool,
+}
+
+/// The unblocking function of a wait, called by Ruby from another thread to interrupt it.
+unsafe extern "C" fn interrupt(data: *mut c_void) {
+    let waiter = &*(data as *const Waiter);
+    waiter.interrupted.store(true, Ordering::Release);
+    waiter.thread.unpark();
+}
+
+/// Handles the interrupts pending for the current thread, returning the exception raised by
+/// Thread#raise, Thread#kill or a signal handler, if any.
+fn check_interrupts() -> Result<(), Error> {
+    magnus::rb_sys::protect(|| {
+        unsafe { rb_thread_check_ints() };
+        Qnil as VALUE
+    })
+    .map(|_| ())
+}
+
+/// Polls `poll` with the GVL released until it returns Some, sleeping a little longer after
+/// each miss. `poll` must not touch any Ruby object or call into the Ruby API.
+///
+/// Unlike without_gvl, the wait can be interrupted: when Ruby has an interrupt for the thread,
+/// the GVL is re-acquired to handle it, returning its exception if it raises and resuming the
+/// wait otherwise.
+pub fn wait_without_gvl<F, R>(mut poll: F) -> Result<R, Error>
+where
+    F: FnMut() -> Option<R>,
+{
+    loop {
+        let waiter = Waiter {
+            thread: thread::current(),
+            interrupted: AtomicBool::new(false),
+        };
+        let wait = || {
+            let mut delay = MIN_POLL;
+            loop {
+                if let Some(result) = poll() {
+                    return Some(result);
+                }
+                if waiter.interrupted.load(Ordering::Acquire) {
+                    return None;
+                }
+                thread::park_timeout(delay);
+                delay = (delay * 2).min(MAX_POLL);
+            }
+        };
+        let data = &waiter as *const Waiter as *mut c_void;
+
+        if let Ok(Some(result)) = call_without_gvl(wait, Some(interrupt), data) {
+            return Ok(result);
+        }
+        check_interrupts()?;
+    }
+}
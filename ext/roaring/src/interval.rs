@@ -0,0 +1,173 @@
+//! The interval string format, such as `1-5,7,9-12`: comma-separated integers and inclusive
+//! ranges of integers, in any order. Whitespace is allowed around integers and separators.
+
+use std::{
+    fmt::{self, Display, Write as _},
+    io,
+    ops::RangeInclusive,
+    str::FromStr,
+};
+
+/// Why an interval string couldn't be parsed, and where.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// The byte offset of the problem within the input.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn error(&self, offset: usize, message: String) -> ParseError {
+        ParseError { offset, message }
+    }
+
+    /// Describes the next character for error messages.
+    fn found(&self) -> String {
+        match self.peek() {
+            Some(c) => format!("found {:?}", c),
+            None => "found the end of the input".into(),
+        }
+    }
+
+    fn integer<T: FromStr>(&mut self) -> Result<(usize, T), ParseError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error(start, format!("expected an integer, {}", self.found())));
+        }
+
+        let digits = &self.input[start..self.pos];
+        let value = digits
+            .parse()
+            .map_err(|_| self.error(start, format!("integer {} is out of range", digits)))?;
+        Ok((start, value))
+    }
+}
+
+/// Parses an interval string, passing every range to `insert`. An empty or blank string has no
+/// ranges.
+pub fn parse<T>(input: &str, mut insert: impl FnMut(RangeInclusive<T>)) -> Result<(), ParseError>
+where
+    T: FromStr + Ord + Copy + Display,
+{
+    let mut parser = Parser { input, pos: 0 };
+    parser.skip_whitespace();
+    if parser.peek().is_none() {
+        return Ok(());
+    }
+
+    loop {
+        let (offset, start) = parser.integer::<T>()?;
+        parser.skip_whitespace();
+        let end = if parser.peek() == Some('-') {
+            parser.pos += 1;
+            let (_, end) = parser.integer::<T>()?;
+            if end < start {
+                return Err(parser.error(
+                    offset,
+                    format!("range {}-{} ends before it starts", start, end),
+                ));
+            }
+            end
+        } else {
+            start
+        };
+        insert(start..=end);
+
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => return Ok(()),
+            Some(',') => parser.pos += 1,
+            Some(_) => {
+                return Err(parser.error(
+                    parser.pos,
+                    format!("expected \",\" or \"-\", {}", parser.found()),
+                ))
+            }
+        }
+    }
+}
+
+/// A range formatted as in an interval string, `7` or `9-12`.
+struct Interval<T>(RangeInclusive<T>);
+
+impl<T: Display + PartialEq> Display for Interval<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, end) = (self.0.start(), self.0.end());
+        if start == end {
+            write!(f, "{}", start)
+        } else {
+            write!(f, "{}-{}", start, end)
+        }
+    }
+}
+
+/// Writes ranges as an interval string, one range at a time.
+pub fn write<T: Display + PartialEq>(
+    mut out: impl io::Write,
+    ranges: impl Iterator<Item = RangeInclusive<T>>,
+) -> io::Result<()> {
+    for (i, range) in ranges.enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        write!(out, "{}", Interval(range))?;
+    }
+
+    Ok(())
+}
+
+/// The number of runs previewed by `inspect`.
+const INSPECT_RANGES: usize = 10;
+
+/// Describes a bitmap for `inspect`, with the bounds of its values and its first few runs, such
+/// as `#<Roaring::Bitmap cardinality=6 range=1..10 bytes=28 [1-3, 7, 9-10]>`.
+pub fn inspect<T: Display + PartialEq>(
+    class: &str,
+    len: u64,
+    bounds: Option<(T, T)>,
+    bytes: usize,
+    ranges: impl Iterator<Item = RangeInclusive<T>>,
+) -> String {
+    let mut out = format!("#<{} cardinality={}", class, len);
+    if let Some((min, max)) = bounds {
+        write!(out, " range={}..{}", min, max).unwrap();
+    }
+    write!(out, " bytes={} [", bytes).unwrap();
+    for (i, range) in ranges.take(INSPECT_RANGES + 1).enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        if i == INSPECT_RANGES {
+            out.push_str("...");
+        } else {
+            write!(out, "{}", Interval(range)).unwrap();
+        }
+    }
+    out.push_str("]>");
+    out
+}
@@ -1,59 +1,518 @@
 #![allow(rustdoc::broken_intra_doc_links)]
 #![allow(rustdoc::invalid_html_tags)]
 #![allow(rustdoc::bare_urls)]
+mod bitmap;
+mod bitmap64;
+mod clickhouse;
+mod cursor;
+mod envelope;
+mod errors;
+mod frozen_bitmap;
+mod gvl;
+mod interval;
+mod lock;
+mod portable;
+mod ruby_io;
+mod view;
+
 use std::{
-    cell::RefCell,
-    ops::{BitAnd, BitOr, BitXor, Sub},
+    io::{BufWriter, Cursor, Read, Write},
+    ops::RangeInclusive,
+    ptr,
 };
 
 use magnus::{
-    block::*, define_module, function, method, prelude::*, typed_data::Obj, DataTypeFunctions,
-    Error, RArray, RString, TypedData, Value,
+    block::*,
+    class, define_module, exception, function, memoize, method,
+    prelude::*,
+    rb_sys::{AsRawValue, FromRawValue},
+    scan_args::{get_kwargs, scan_args},
+    typed_data::Obj,
+    value::BoxValue,
+    DataType, DataTypeFunctions, Error, Integer, IntoValue, RArray, RClass, RHash, RModule,
+    RString, Range, Symbol, TryConvert, TypedData, Value,
+};
+use rb_sys::{
+    rb_data_typed_object_wrap, rb_define_alloc_func, rb_enumeratorize_with_size, rb_funcallv,
+    rb_id2sym, rb_intern2, VALUE,
 };
-use roaring::RoaringBitmap;
+use roaring::{RoaringBitmap, RoaringTreemap};
+
+use crate::{
+    bitmap::Bitmap,
+    cursor,
+    envelope::Compression,
+    frozen_bitmap::FrozenBitmap,
+    interval,
+    lock::Lock,
+    ruby_io::{self, RubyReader, RubyWriter},
+};
+
+/// Whether a Ruby Range ends before 0, like `0...0` or `5..-3`, so it holds no integer a bitmap
+/// can contain whatever its start.
+pub(crate) fn ends_before_zero(range: Range) -> Result<bool, Error> {
+    let last = if range.excl() { 0 } else { -1 };
+    match Integer::from_value(range.end()?) {
+        // Compared by Ruby, as the end may be a Bignum.
+        Some(end) => end.funcall("<=", (last,)),
+        None => Ok(false),
+    }
+}
+
+/// Converts a Ruby Range into an inclusive range of integers of a bitmap, or None if the range is
+/// empty. Beginless and endless ranges extend to 0 and the largest integer respectively.
+fn range_to_inclusive<B: Bitmap>(range: Range) -> Result<Option<RangeInclusive<B::Value>>, Error> {
+    let start = range.beg::<Option<B::Value>>()?.unwrap_or_default();
+    if ends_before_zero(range)? {
+        return Ok(None);
+    }
+    let end = match range.end::<Option<u64>>()? {
+        None => B::MAX,
+        Some(end) => {
+            let end = if range.excl() { end - 1 } else { end };
+            B::Value::try_from(end).map_err(|_| {
+                Error::new(
+                    exception::range_error(),
+                    format!(
+                        "range end {} is out of bounds for a {}-bit bitmap",
+                        end,
+                        B::BITS
+                    ),
+                )
+            })?
+        }
+    };
+
+    Ok((start <= end).then_some(start..=end))
+}
+
+/// Converts the elements of an Array. Unlike `RArray::to_vec`, also converts them into Ruby
+/// objects such as Value and Obj.
+pub(crate) fn entries<T: TryConvert>(array: RArray) -> Result<Vec<T>, Error> {
+    (0..array.len())
+        .map(|i| array.entry::<T>(i as isize))
+        .collect()
+}
+
+/// Converts the elements of an Array into integers of a bitmap, raising
+/// Roaring::InvalidValueError for anything else.
+fn integers<T: TryConvert>(array: RArray) -> Result<Vec<T>, Error> {
+    entries(array).map_err(|e| Error::new(errors::invalid_value_error(), e.to_string()))
+}
+
+/// Roaring::ConcurrentBitmap, the thread-friendly subclass of Roaring::Bitmap.
+fn concurrent_bitmap_class() -> RClass {
+    *memoize!(RClass: {
+        let module: RModule = class::object().const_get("Roaring").unwrap();
+        module.const_get("ConcurrentBitmap").unwrap()
+    })
+}
+
+/// Raises FrozenError if `obj` is frozen, like Ruby's own mutating methods.
+pub(crate) fn check_frozen(obj: Value) -> Result<(), Error> {
+    if obj.is_frozen() {
+        return Err(Error::new(
+            exception::frozen_error(),
+            format!(
+                "can't modify frozen {}: {}",
+                unsafe { obj.classname() },
+                obj.inspect()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Parses the `compression:`, `level:` and `checksum:` options of `serialize`.
+pub(crate) fn serialize_options(args: &[Value]) -> Result<(Compression, bool), Error> {
+    let args = scan_args::<(), (), (), (), RHash, ()>(args)?;
+    let kwargs = get_kwargs::<_, (), (Option<Symbol>, Option<i32>, Option<bool>), ()>(
+        args.keywords,
+        &[],
+        &["compression", "level", "checksum"],
+    )?;
+    let (compression, level, checksum) = kwargs.optional;
+    let compression = compression.map(|sym| sym.name()).transpose()?;
+
+    let compression = match (compression.as_deref(), level) {
+        (None, None) => Ok(Compression::None),
+        (Some("zstd"), level) => Ok(Compression::Zstd(
+            level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL),
+        )),
+        (Some("lz4"), None) => Ok(Compression::Lz4),
+        (Some("lz4"), Some(_)) => Err(Error::new(
+            exception::arg_error(),
+            "lz4 compression doesn't support levels",
+        )),
+        (None, Some(_)) => Err(Error::new(
+            exception::arg_error(),
+            "level requires compression: :zstd",
+        )),
+        (Some(other), _) => Err(Error::new(
+            exception::arg_error(),
+            format!("unknown compression :{}, expected :zstd or :lz4", other),
+        )),
+    }?;
+    Ok((compression, checksum.unwrap_or(false)))
+}
+
+/// Parses the `pairs:` option of the range methods.
+pub(crate) fn pairs_option(args: &[Value]) -> Result<bool, Error> {
+    let args = scan_args::<(), (), (), (), RHash, ()>(args)?;
+    let kwargs = get_kwargs::<_, (), (Option<bool>,), ()>(args.keywords, &[], &["pairs"])?;
+    Ok(kwargs.optional.0.unwrap_or(false))
+}
+
+/// Converts a run of integers into an inclusive Range, or a `[start, end]` Array with `pairs`.
+fn range_value<T: IntoValue>(range: RangeInclusive<T>, pairs: bool) -> Result<Value, Error> {
+    let (start, end) = range.into_inner();
+    if pairs {
+        let pair = RArray::with_capacity(2);
+        pair.push(start)?;
+        pair.push(end)?;
+        Ok(pair.into_value())
+    } else {
+        Ok(Range::new(start, end, false)?.into_value())
+    }
+}
+
+/// Yields every run of integers to the block, stopping at the first exception.
+pub(crate) fn yield_ranges<T: IntoValue>(
+    block: &Proc,
+    ranges: impl Iterator<Item = RangeInclusive<T>>,
+    pairs: bool,
+) -> Result<(), Error> {
+    for range in ranges {
+        block.call::<_, Option<Value>>((range_value(range, pairs)?,))?;
+    }
+
+    Ok(())
+}
+
+/// Collects the runs of integers into an Array.
+pub(crate) fn ranges_array<T: IntoValue>(
+    ranges: impl Iterator<Item = RangeInclusive<T>>,
+    pairs: bool,
+) -> Result<RArray, Error> {
+    let array = RArray::new();
+    for range in ranges {
+        array.push(range_value(range, pairs)?)?;
+    }
+
+    Ok(array)
+}
+
+/// Checks the `limit` of a page, which must be positive.
+pub(crate) fn page_limit(limit: usize) -> Result<usize, Error> {
+    if limit == 0 {
+        return Err(Error::new(exception::arg_error(), "limit must be positive"));
+    }
+
+    Ok(limit)
+}
+
+/// Parses the `after:` and `limit:` options of `page`, and returns the integer the `after:`
+/// cursor was made from. `after: nil` starts from the beginning.
+pub(crate) fn page_options(args: &[Value]) -> Result<(Option<u64>, usize), Error> {
+    let args = scan_args::<(), (), (), (), RHash, ()>(args)?;
+    let kwargs = get_kwargs::<_, (usize,), (Option<Option<Value>>,), ()>(
+        args.keywords,
+        &["limit"],
+        &["after"],
+    )?;
+    let after = kwargs.optional.0.flatten().map(decode_cursor).transpose()?;
+    Ok((after, page_limit(kwargs.required.0)?))
+}
+
+/// Decodes a cursor returned by `page`, raising ArgumentError for anything else.
+fn decode_cursor(after: Value) -> Result<u64, Error> {
+    RString::from_value(after)
+        .and_then(|string| string.to_string().ok())
+        .and_then(|string| cursor::decode(&string))
+        .ok_or_else(|| {
+            Error::new(
+                exception::arg_error(),
+                format!("invalid cursor {}", after.inspect()),
+            )
+        })
+}
+
+/// Takes up to `limit` integers from `iter` into an Array, and returns it with the last integer
+/// taken if more integers follow it.
+pub(crate) fn take_page<T: IntoValue + Copy>(
+    mut iter: impl Iterator<Item = T>,
+    limit: usize,
+) -> Result<(RArray, Option<T>), Error> {
+    let values = RArray::new();
+    let mut last = None;
+    for value in iter.by_ref().take(limit) {
+        values.push(value)?;
+        last = Some(value);
+    }
+
+    Ok((values, last.filter(|_| iter.next().is_some())))
+}
+
+/// Builds the `[values, next_cursor]` pair returned by the pagination methods.
+pub(crate) fn page_result<T: IntoValue>(values: RArray, next: Option<T>) -> Result<RArray, Error> {
+    let page = RArray::with_capacity(2);
+    page.push(values)?;
+    page.push(next)?;
+    Ok(page)
+}
+
+/// Converts an element of `from_ranges` into a Range, or a `[start, end]` pair as a Range.
+pub(crate) fn range_from_element(element: Value) -> Result<Range, Error> {
+    if let Some(range) = Range::from_value(element) {
+        return Ok(range);
+    }
+
+    let pair = RArray::from_value(element)
+        .map(entries::<Value>)
+        .transpose()?;
+    match pair.as_deref() {
+        Some(&[start, end]) => Range::new(start, end, false),
+        _ => Err(Error::new(
+            exception::type_error(),
+            format!(
+                "expected a Range or a [start, end] pair, got {}",
+                element.inspect()
+            ),
+        )),
+    }
+}
+
+/// Yields every item to the block of the current method, stopping at the first exception.
+///
+/// Items are passed to `rb_yield` as they are, without building an argument Array or a Proc
+/// for the block.
+pub(crate) fn yield_all<T: IntoValue>(items: impl Iterator<Item = T>) -> Result<(), Error> {
+    for i in items {
+        yield_value::<T, Value>(i)?;
+    }
+
+    Ok(())
+}
+
+/// Returns an Enumerator over the `method` of `obj`, whose `size` is the cardinality of `obj`
+/// at the time it's asked for.
+pub(crate) fn enumerator_with_size(obj: Value, method: &str) -> Value {
+    unsafe extern "C" fn cardinality(obj: VALUE, _args: VALUE, _enumerator: VALUE) -> VALUE {
+        let name = "cardinality";
+        rb_funcallv(
+            obj,
+            rb_intern2(name.as_ptr().cast(), name.len() as _),
+            0,
+            ptr::null(),
+        )
+    }
+
+    unsafe {
+        let method = rb_id2sym(rb_intern2(method.as_ptr().cast(), method.len() as _));
+        Value::from_raw(rb_enumeratorize_with_size(
+            obj.as_raw(),
+            method,
+            0,
+            ptr::null(),
+            Some(cardinality),
+        ))
+    }
+}
+
+/// Wraps `data` in a new object of `class`, which is `T::class()` or one of its subclasses.
+/// magnus 0.5 only wraps data in the class of its type.
+pub(crate) fn wrap_as<T: TypedData>(data: T, class: RClass) -> Obj<T> {
+    let data = Box::into_raw(Box::new(data));
+    unsafe {
+        // DataType is a transparent wrapper around rb_data_type_t, and the object is freed by
+        // the free function of the data type, like the ones magnus wraps.
+        let obj = rb_data_typed_object_wrap(
+            class.as_raw(),
+            data.cast(),
+            (T::data_type() as *const DataType).cast(),
+        );
+        Obj::try_convert(Value::from_raw(obj)).unwrap()
+    }
+}
+
+/// Defines the allocator of `class`, which wraps `T::default()`, so that `new`, `dup` and
+/// `clone` work on the class and its subclasses. magnus 0.5 has no way of defining one.
+pub(crate) fn define_alloc_func<T: Default + TypedData>(class: RClass) {
+    unsafe extern "C" fn allocate<T: Default + TypedData>(class: VALUE) -> VALUE {
+        let class = RClass::from_value(Value::from_raw(class)).unwrap();
+        wrap_as(T::default(), class).as_raw()
+    }
 
-struct Wrapper {
-    _data: roaring::RoaringBitmap,
+    unsafe { rb_define_alloc_func(class.as_raw(), Some(allocate::<T>)) }
+}
+
+struct Wrapper<B> {
+    _data: B,
 }
 
 /// @yard
+/// @rename Roaring::Bitmap
 /// Roaring::Bitmap is a fast, compressed bitmap implementation.
-#[derive(TypedData)]
-#[magnus(class = "Roaring::Bitmap", free_immediately, size)]
-struct MutWrapper(RefCell<Wrapper>);
+///
+/// A frozen bitmap is immutable: every mutating method raises FrozenError. Frozen bitmaps
+/// are shareable, so `Ractor.make_shareable(bitmap)` lets any Ractor read them.
+///
+/// Roaring::Bitmap holds integers in 0...2**32, and Roaring::Bitmap64 integers in 0...2**64,
+/// with the same methods but ::full and ::mmap.
+struct MutWrapper<B>(Lock<Wrapper<B>>);
+
+// Not derived, as the derived class() undefines the allocator of the class.
+unsafe impl TypedData for MutWrapper<RoaringBitmap> {
+    fn class() -> RClass {
+        *memoize!(RClass: {
+            let module: RModule = class::object().const_get("Roaring").unwrap();
+            module.const_get("Bitmap").unwrap()
+        })
+    }
 
-impl DataTypeFunctions for MutWrapper {
+    fn data_type() -> &'static DataType {
+        memoize!(DataType: {
+            let mut builder = DataType::builder::<Self>("Roaring::Bitmap");
+            builder.size();
+            builder.free_immediately();
+            builder.frozen_shareable();
+            builder.build()
+        })
+    }
+}
+
+unsafe impl TypedData for MutWrapper<RoaringTreemap> {
+    fn class() -> RClass {
+        *memoize!(RClass: {
+            let module: RModule = class::object().const_get("Roaring").unwrap();
+            module.const_get("Bitmap64").unwrap()
+        })
+    }
+
+    fn data_type() -> &'static DataType {
+        memoize!(DataType: {
+            let mut builder = DataType::builder::<Self>("Roaring::Bitmap64");
+            builder.size();
+            builder.free_immediately();
+            builder.frozen_shareable();
+            builder.build()
+        })
+    }
+}
+
+impl<B: Bitmap> DataTypeFunctions for MutWrapper<B> {
     fn size(&self) -> usize {
-        self.byte_size().unwrap_or(0)
+        self.0
+            .try_read()
+            .map(|data| data._data.serialized_size())
+            .unwrap_or(0)
     }
 }
 
-impl MutWrapper {
-    /// @yard
-    ///
-    /// Initializes a new empty bitmap.
-    ///
-    /// @return [Roaring::Bitmap] A new empty bitmap.
-    fn new() -> Self {
-        Self(RefCell::new(Wrapper {
-            _data: RoaringBitmap::new(),
+impl<B: Bitmap> Default for MutWrapper<B> {
+    fn default() -> Self {
+        Self(Lock::new(Wrapper {
+            _data: B::default(),
         }))
     }
+}
+
+impl<B: Bitmap> MutWrapper<B>
+where
+    Self: TypedData,
+{
+    fn new(data: B) -> Self {
+        Self(Lock::new(Wrapper { _data: data }))
+    }
+
+    /// Wraps a bitmap in a new object of the given class, which is the class of the bitmap or
+    /// one of its subclasses.
+    fn wrap_as(data: B, class: RClass) -> Obj<Self> {
+        wrap_as(Self::new(data), class)
+    }
+
+    /// Passes the bitmap to `func`, which calls back into Ruby. Roaring::ConcurrentBitmap passes
+    /// a snapshot, so that other threads can keep writing; any other bitmap is marked as being
+    /// iterated, so that writers raise instead of waiting for the lock held during `func`.
+    fn read_for_callbacks<R>(
+        rb_self: Obj<Self>,
+        func: impl FnOnce(&B) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let this = rb_self.get();
+        if rb_self.is_kind_of(concurrent_bitmap_class()) {
+            let data = this.0.read()?._data.clone();
+            func(&data)
+        } else {
+            let _iterating = this.0.iterate();
+            let data = this.0.read()?;
+            func(&data._data)
+        }
+    }
+
+    /// Collects the integers of an Array, Range, Roaring::Bitmap or any other Enumerable.
+    fn collect(source: Value) -> Result<B, Error> {
+        if let Ok(other) = Obj::<Self>::try_convert(source) {
+            return Ok(other.get().0.read()?._data.clone());
+        }
+
+        if let Some(range) = Range::from_value(source) {
+            let mut data = B::default();
+            if let Some(r) = range_to_inclusive::<B>(range)? {
+                data.insert_range(r)?;
+            }
+            return Ok(data);
+        }
+
+        let array = match RArray::from_value(source) {
+            Some(array) => array,
+            None if source.respond_to("each", false)? => source.funcall("to_a", ())?,
+            None => {
+                return Err(Error::new(
+                    exception::type_error(),
+                    format!(
+                        "no implicit conversion of {} into {}",
+                        unsafe { source.classname() },
+                        Self::class().inspect()
+                    ),
+                ))
+            }
+        };
+        Ok(B::from_values(integers(array)?))
+    }
 
     /// @yard
-    /// @def full
-    ///
-    /// Initializes a new bitmap with all bits set to 1.
-    ///
-    /// @example Initialize a full bitmap.
-    ///     rb = Roaring::Bitmap.full
-    ///     rb.full?    #=> true
-    ///
-    /// @return [Roaring::Bitmap] A new full bitmap.
-    fn new_full() -> Self {
-        Self(RefCell::new(Wrapper {
-            _data: RoaringBitmap::full(),
-        }))
+    /// @def initialize(source = nil)
+    ///
+    /// Initializes a new bitmap, empty or holding the integers of `source`.
+    ///
+    /// @param source [Array<Integer>, Range<Integer>, Set<Integer>, Roaring::Bitmap, Enumerable, nil]
+    ///     The integers to initialize the bitmap with.
+    ///
+    /// @example Initialize a bitmap.
+    ///     Roaring::Bitmap.new.to_a                    #=> []
+    ///     Roaring::Bitmap.new([3, 1, 2]).to_a         #=> [1, 2, 3]
+    ///     Roaring::Bitmap.new(1...4).to_a             #=> [1, 2, 3]
+    ///     Roaring::Bitmap.new(Set[1, 2, 3]).to_a      #=> [1, 2, 3]
+    ///
+    /// @example Subclasses keep their class.
+    ///     class SegmentBitmap < Roaring::Bitmap; end
+    ///     SegmentBitmap.new([1, 2]) | SegmentBitmap.new([3])   #=> #<SegmentBitmap ...>
+    ///
+    /// @return [void]
+    /// @raise [Roaring::InvalidValueError] If `source` contains anything but integers in 0...2**32.
+    /// @raise [TypeError] If `source` is not a collection of integers.
+    fn initialize(rb_self: Obj<Self>, args: &[Value]) -> Result<(), Error> {
+        check_frozen(*rb_self)?;
+        let args = scan_args::<(), (Option<Value>,), (), (), (), ()>(args)?;
+        if let (Some(source),) = args.optional {
+            if !source.is_nil() {
+                let data = Self::collect(source)?;
+                rb_self.get().0.write()?._data = data;
+            }
+        }
+
+        Ok(())
     }
 
     /// @yard
@@ -68,11 +527,38 @@ impl MutWrapper {
     ///     rb.to_a     #=> [1, 2, 3]
     ///
     /// @return [Roaring::Bitmap] A new bitmap initialized from the array.
-    fn from_array(array: RArray) -> Self {
-        let values = array.to_vec::<u32>().unwrap();
-        Self(RefCell::new(Wrapper {
-            _data: RoaringBitmap::from_iter(values),
-        }))
+    /// @raise [Roaring::InvalidValueError] If the array contains anything but integers in 0...2**32.
+    fn from_array(class: RClass, array: RArray) -> Result<Obj<Self>, Error> {
+        Ok(Self::wrap_as(B::from_values(integers(array)?), class))
+    }
+
+    /// @yard
+    /// @def initialize_copy(other)
+    ///
+    /// Replaces the contents of this bitmap with a copy of another one. Called by `dup` and
+    /// `clone`, so the copy doesn't share any data with the original. `dup` returns an unfrozen
+    /// copy, while `clone` keeps the frozen state unless called with `freeze: false`.
+    ///
+    /// @example Branching off a working copy.
+    ///     rb = Roaring::Bitmap.from_a([1, 2, 3])
+    ///     copy = rb.dup
+    ///     copy.remove(1)
+    ///     rb.to_a     #=> [1, 2, 3]
+    ///     copy.to_a   #=> [2, 3]
+    ///
+    /// @return [Roaring::Bitmap] self.
+    fn initialize_copy(rb_self: Obj<Self>, other: &Self) -> Result<Value, Error> {
+        check_frozen(*rb_self)?;
+        let this = rb_self.get();
+        if !std::ptr::eq(this, other) {
+            let data = {
+                let source = other.0.read()?;
+                gvl::without_gvl_if_large(source._data.len(), || source._data.clone())
+            };
+            this.0.write()?._data = data;
+        }
+
+        Ok(*rb_self)
     }
 
     /// @yard
@@ -92,8 +578,9 @@ impl MutWrapper {
     ///     rb.insert(1)    #=> false
     ///
     /// @return [Boolean] true if the item was not already present, false otherwise.
-    fn insert(&self, item: u32) -> Result<bool, Error> {
-        Ok(self.0.borrow_mut()._data.insert(item))
+    fn insert(rb_self: Obj<Self>, item: B::Value) -> Result<bool, Error> {
+        check_frozen(*rb_self)?;
+        Ok(rb_self.get().0.write()?._data.insert(item))
     }
 
     /// @yard
@@ -109,11 +596,14 @@ impl MutWrapper {
     ///     rb.to_a                     #=> [1, 2, 3]
     ///
     /// @return [Integer] The number of items that were inserted.
-    fn insert_many(&self, items: RArray) -> Result<u32, Error> {
-        let values = items.to_vec::<u32>()?;
+    /// @raise [Roaring::InvalidValueError] If the array contains anything but integers in 0...2**32.
+    fn insert_many(rb_self: Obj<Self>, items: RArray) -> Result<u64, Error> {
+        check_frozen(*rb_self)?;
+        let values = integers::<B::Value>(items)?;
+        let mut data = rb_self.get().0.write()?;
         let mut inserted = 0;
         for value in values {
-            if self.0.borrow_mut()._data.insert(value) {
+            if data._data.insert(value) {
                 inserted += 1;
             }
         }
@@ -138,8 +628,9 @@ impl MutWrapper {
     ///     rb.remove(1)    #=> false
     ///
     /// @return [Boolean] true if the item was present, false otherwise.
-    fn remove(&self, item: u32) -> Result<bool, Error> {
-        Ok(self.0.borrow_mut()._data.remove(item))
+    fn remove(rb_self: Obj<Self>, item: B::Value) -> Result<bool, Error> {
+        check_frozen(*rb_self)?;
+        Ok(rb_self.get().0.write()?._data.remove(item))
     }
 
     /// @yard
@@ -159,8 +650,94 @@ impl MutWrapper {
     ///     rb.contains(1)  #=> false
     ///
     /// @return [Boolean] true if the item is present, false otherwise.
-    fn contains(&self, item: u32) -> Result<bool, Error> {
-        Ok(self.0.borrow()._data.contains(item))
+    fn contains(&self, item: B::Value) -> Result<bool, Error> {
+        Ok(self.0.read()?._data.contains(item))
+    }
+
+    /// @yard
+    /// @def insert_range(range)
+    ///
+    /// Inserts every integer of a range into the bitmap.
+    ///
+    /// @param [Range<Integer>] range The range to insert. Exclusive, beginless and endless ranges are supported.
+    ///
+    /// @example Insert a range into the bitmap.
+    ///     rb = Roaring::Bitmap.new
+    ///     rb.insert_range(1..3)   #=> 3
+    ///     rb.insert_range(2...5)  #=> 2
+    ///     rb.to_a                 #=> [1, 2, 3, 4]
+    ///
+    /// @return [Integer] The number of items that were inserted.
+    /// @raise [RangeError] If the range is out of bounds, or spans all 2**64 integers of a
+    ///     Roaring::Bitmap64.
+    fn insert_range(rb_self: Obj<Self>, range: Range) -> Result<u64, Error> {
+        check_frozen(*rb_self)?;
+        match range_to_inclusive::<B>(range)? {
+            Some(r) => rb_self.get().0.write()?._data.insert_range(r),
+            None => Ok(0),
+        }
+    }
+
+    /// @yard
+    /// @def remove_range(range)
+    ///
+    /// Removes every integer of a range from the bitmap.
+    ///
+    /// @param [Range<Integer>] range The range to remove. Exclusive, beginless and endless ranges are supported.
+    ///
+    /// @example Remove a range from the bitmap.
+    ///     rb = Roaring::Bitmap.from_a([1, 2, 3, 4, 5])
+    ///     rb.remove_range(2..)    #=> 4
+    ///     rb.to_a                 #=> [1]
+    ///
+    /// @return [Integer] The number of items that were removed.
+    fn remove_range(rb_self: Obj<Self>, range: Range) -> Result<u64, Error> {
+        check_frozen(*rb_self)?;
+        match range_to_inclusive::<B>(range)? {
+            Some(r) => Ok(rb_self.get().0.write()?._data.remove_range(r)),
+            None => Ok(0),
+        }
+    }
+
+    /// @yard
+    /// @def contains_range?(range)
+    ///
+    /// Checks if every integer of a range is present in the bitmap. An empty range is always contained.
+    ///
+    /// @param [Range<Integer>] range The range to check for.
+    ///
+    /// @example Check the bitmap for the presence of a range.
+    ///     rb = Roaring::Bitmap.new
+    ///     rb.insert_range(1..10)
+    ///     rb.contains_range?(2...10)  #=> true
+    ///     rb.contains_range?(0..10)   #=> false
+    ///
+    /// @return [Boolean] true if the whole range is present, false otherwise.
+    fn contains_range(&self, range: Range) -> Result<bool, Error> {
+        match range_to_inclusive::<B>(range)? {
+            Some(r) => Ok(self.0.read()?._data.contains_range(r)),
+            None => Ok(true),
+        }
+    }
+
+    /// @yard
+    /// @def range_cardinality(range)
+    ///
+    /// Returns the number of items in the bitmap that fall within a range.
+    ///
+    /// @param [Range<Integer>] range The range to count items in.
+    ///
+    /// @example Count the items within a range.
+    ///     rb = Roaring::Bitmap.from_a([1, 5, 10, 20])
+    ///     rb.range_cardinality(5..10)     #=> 2
+    ///     rb.range_cardinality(5...10)    #=> 1
+    ///
+    /// @return [Integer] The number of items in the range.
+    fn range_cardinality(&self, range: Range) -> Result<u64, Error> {
+        match range_to_inclusive::<B>(range)? {
+            Some(r) => Ok(self.0.read()?._data.range_cardinality(r)),
+            None => Ok(0),
+        }
     }
 
     /// @yard
@@ -174,8 +751,8 @@ impl MutWrapper {
     ///     rb.to_a     #=> [1, 2, 3]
     ///
     /// @return [Array<Integer>] An array of all the items in the bitmap.
-    fn to_vec(&self) -> Result<Vec<u32>, Error> {
-        Ok(self.0.borrow()._data.iter().collect())
+    fn to_vec(&self) -> Result<Vec<B::Value>, Error> {
+        Ok(self.0.read()?._data.iter().collect())
     }
 
     /// @yard
@@ -191,8 +768,9 @@ impl MutWrapper {
     ///     rb.to_a     #=> []
     ///
     /// @return [nil]
-    fn clear(&self) -> Result<(), Error> {
-        self.0.borrow_mut()._data.clear();
+    fn clear(rb_self: Obj<Self>) -> Result<(), Error> {
+        check_frozen(*rb_self)?;
+        rb_self.get().0.write()?._data.clear();
         Ok(())
     }
 
@@ -208,7 +786,7 @@ impl MutWrapper {
     ///
     /// @return [Integer] The number of items in the bitmap.
     fn len(&self) -> Result<u64, Error> {
-        Ok(self.0.borrow()._data.len())
+        Ok(self.0.read()?._data.len())
     }
 
     /// @yard
@@ -227,7 +805,7 @@ impl MutWrapper {
     ///
     /// @return [Boolean] true if the bitmap is empty, false otherwise.
     fn is_empty(&self) -> Result<bool, Error> {
-        Ok(self.0.borrow()._data.is_empty())
+        Ok(self.0.read()?._data.is_empty())
     }
 
     /// @yard
@@ -246,7 +824,7 @@ impl MutWrapper {
     ///
     /// @return [Boolean] true if the bitmap is full, false otherwise.
     fn is_full(&self) -> Result<bool, Error> {
-        Ok(self.0.borrow()._data.is_full())
+        Ok(self.0.read()?._data.is_full())
     }
 
     /// @yard
@@ -264,8 +842,8 @@ impl MutWrapper {
     ///     rb.max  #=> nil
     ///
     /// @return [Integer, nil] The maximum value in the bitmap, or nil if the bitmap is empty.
-    fn max(&self) -> Result<Option<u32>, Error> {
-        Ok(self.0.borrow()._data.max())
+    fn max(&self) -> Result<Option<B::Value>, Error> {
+        Ok(self.0.read()?._data.max())
     }
 
     /// @yard
@@ -283,8 +861,8 @@ impl MutWrapper {
     ///     rb.min  #=> nil
     ///
     /// @return [Integer, nil] The minimum value in the bitmap, or nil if the bitmap is empty.
-    fn min(&self) -> Result<Option<u32>, Error> {
-        Ok(self.0.borrow()._data.min())
+    fn min(&self) -> Result<Option<B::Value>, Error> {
+        Ok(self.0.read()?._data.min())
     }
 
     /// @yard
@@ -302,8 +880,8 @@ impl MutWrapper {
     ///     rb.nth(1)   #=> nil
     ///
     /// @return [Integer, nil] The nth integer in the bitmap, or nil if the bitmap is empty or if n is out of bounds.
-    fn select(&self, item: u32) -> Result<Option<u32>, Error> {
-        Ok(self.0.borrow()._data.select(item))
+    fn select(&self, item: B::Value) -> Result<Option<B::Value>, Error> {
+        Ok(self.0.read()?._data.select(item.into()))
     }
 
     /// @yard
@@ -329,7 +907,8 @@ impl MutWrapper {
     ///
     /// @return [Boolean] true if the bitmaps are disjoint, false otherwise.
     fn is_disjoint(&self, other: &Self) -> Result<bool, Error> {
-        Ok(self.0.borrow()._data.is_disjoint(&other.0.borrow()._data))
+        self.0
+            .read_both(&other.0, |lhs, rhs| lhs._data.is_disjoint(&rhs._data))
     }
 
     /// @yard
@@ -355,7 +934,8 @@ impl MutWrapper {
     ///
     /// @return [Boolean] true if the bitmap is a subset of another bitmap, false otherwise.
     fn is_subset(&self, other: &Self) -> Result<bool, Error> {
-        Ok(self.0.borrow()._data.is_subset(&other.0.borrow()._data))
+        self.0
+            .read_both(&other.0, |lhs, rhs| lhs._data.is_subset(&rhs._data))
     }
 
     /// @yard
@@ -381,7 +961,8 @@ impl MutWrapper {
     ///
     /// @return [Boolean] true if the bitmap is a superset of another bitmap, false otherwise.
     fn is_superset(&self, other: &Self) -> Result<bool, Error> {
-        Ok(self.0.borrow()._data.is_superset(&other.0.borrow()._data))
+        self.0
+            .read_both(&other.0, |lhs, rhs| lhs._data.is_superset(&rhs._data))
     }
 
     /// @yard
@@ -401,12 +982,14 @@ impl MutWrapper {
     ///     (rb1 | rb2).to_a    #=> [1, 2, 3, 4, 5]
     ///
     /// @return [Roaring::Bitmap] The union of the bitmap with another bitmap.
-    fn union(&self, other: &Self) -> Result<Self, Error> {
-        let lhs = &self.0.borrow()._data;
-        let rhs = &other.0.borrow()._data;
-        let d = lhs.bitor(rhs);
-
-        Ok(Self(RefCell::new(Wrapper { _data: d })))
+    fn union(rb_self: Obj<Self>, other: &Self) -> Result<Obj<Self>, Error> {
+        let d = rb_self.get().0.read_both(&other.0, |lhs, rhs| {
+            gvl::without_gvl_if_large(lhs._data.len() + rhs._data.len(), || {
+                lhs._data.union(&rhs._data)
+            })
+        })?;
+
+        Ok(Self::wrap_as(d, rb_self.class()))
     }
 
     /// @yard
@@ -426,7 +1009,8 @@ impl MutWrapper {
     ///
     /// @return [Integer] The cardinality of the union of the bitmap with another bitmap.
     fn union_len(&self, other: &Self) -> Result<u64, Error> {
-        Ok(self.0.borrow()._data.union_len(&other.0.borrow()._data))
+        self.0
+            .read_both(&other.0, |lhs, rhs| lhs._data.union_len(&rhs._data))
     }
 
     /// @yard
@@ -446,12 +1030,14 @@ impl MutWrapper {
     ///     (rb1 & rb2).to_a           #=> [3]
     ///
     /// @return [Roaring::Bitmap] The intersection of the bitmap with another bitmap.
-    fn intersection(&self, other: &Self) -> Result<Self, Error> {
-        let lhs = &self.0.borrow()._data;
-        let rhs = &other.0.borrow()._data;
-        let d = lhs.bitand(rhs);
-
-        Ok(Self(RefCell::new(Wrapper { _data: d })))
+    fn intersection(rb_self: Obj<Self>, other: &Self) -> Result<Obj<Self>, Error> {
+        let d = rb_self.get().0.read_both(&other.0, |lhs, rhs| {
+            gvl::without_gvl_if_large(lhs._data.len() + rhs._data.len(), || {
+                lhs._data.intersection(&rhs._data)
+            })
+        })?;
+
+        Ok(Self::wrap_as(d, rb_self.class()))
     }
 
     /// @yard
@@ -471,11 +1057,8 @@ impl MutWrapper {
     ///
     /// @return [Integer] The cardinality of the intersection of the bitmap with another bitmap.
     fn intersection_len(&self, other: &Self) -> Result<u64, Error> {
-        Ok(self
-            .0
-            .borrow()
-            ._data
-            .intersection_len(&other.0.borrow()._data))
+        self.0
+            .read_both(&other.0, |lhs, rhs| lhs._data.intersection_len(&rhs._data))
     }
 
     /// @yard
@@ -495,12 +1078,14 @@ impl MutWrapper {
     ///     (rb1 - rb2).to_a            #=> [1, 2]
     ///
     /// @return [Roaring::Bitmap] The difference of the bitmap with another bitmap.
-    fn difference(&self, other: &Self) -> Result<Self, Error> {
-        let lhs = &self.0.borrow()._data;
-        let rhs = &other.0.borrow()._data;
-        let d = lhs.sub(rhs);
-
-        Ok(Self(RefCell::new(Wrapper { _data: d })))
+    fn difference(rb_self: Obj<Self>, other: &Self) -> Result<Obj<Self>, Error> {
+        let d = rb_self.get().0.read_both(&other.0, |lhs, rhs| {
+            gvl::without_gvl_if_large(lhs._data.len() + rhs._data.len(), || {
+                lhs._data.difference(&rhs._data)
+            })
+        })?;
+
+        Ok(Self::wrap_as(d, rb_self.class()))
     }
 
     /// @yard
@@ -520,11 +1105,8 @@ impl MutWrapper {
     ///
     /// @return [Integer] The cardinality of the difference of the bitmap with another bitmap.
     fn difference_len(&self, other: &Self) -> Result<u64, Error> {
-        Ok(self
-            .0
-            .borrow()
-            ._data
-            .difference_len(&other.0.borrow()._data))
+        self.0
+            .read_both(&other.0, |lhs, rhs| lhs._data.difference_len(&rhs._data))
     }
 
     /// @yard
@@ -544,11 +1126,13 @@ impl MutWrapper {
     ///     (rb1 ^ rb2).to_a                   #=> [1, 2, 4, 5]
     ///
     /// @return [Roaring::Bitmap] The symmetric difference of the bitmap with another bitmap.
-    fn symmetric_difference(&self, other: &Self) -> Result<Self, Error> {
-        let lhs = &self.0.borrow()._data;
-        let rhs = &other.0.borrow()._data;
-        let d = lhs.bitxor(rhs);
-        Ok(Self(RefCell::new(Wrapper { _data: d })))
+    fn symmetric_difference(rb_self: Obj<Self>, other: &Self) -> Result<Obj<Self>, Error> {
+        let d = rb_self.get().0.read_both(&other.0, |lhs, rhs| {
+            gvl::without_gvl_if_large(lhs._data.len() + rhs._data.len(), || {
+                lhs._data.symmetric_difference(&rhs._data)
+            })
+        })?;
+        Ok(Self::wrap_as(d, rb_self.class()))
     }
 
     /// @yard
@@ -568,17 +1152,251 @@ impl MutWrapper {
     ///
     /// @return [Integer] The cardinality of the symmetric difference of the bitmap with another bitmap.
     fn symmetric_difference_len(&self, other: &Self) -> Result<u64, Error> {
-        Ok(self
-            .0
-            .borrow()
-            ._data
-            .symmetric_difference_len(&other.0.borrow()._data))
+        self.0.read_both(&other.0, |lhs, rhs| {
+            lhs._data.symmetric_difference_len(&rhs._data)
+        })
+    }
+
+    /// @yard
+    /// @def union!(other)
+    ///
+    /// Unions another bitmap into this one, in place. Bitwise OR.
+    ///
+    /// @param other [Roaring::Bitmap] The other bitmap to union with.
+    ///
+    /// @example Unioning a bitmap in place.
+    ///     rb1 = Roaring::Bitmap.from_a([1, 2, 3])
+    ///     rb2 = Roaring::Bitmap.from_a([3, 4, 5])
+    ///     rb1.union!(rb2)     #=> rb1
+    ///     rb1.to_a            #=> [1, 2, 3, 4, 5]
+    ///
+    /// @return [Roaring::Bitmap] self.
+    fn union_inplace(rb_self: Obj<Self>, other: &Self) -> Result<Value, Error> {
+        check_frozen(*rb_self)?;
+        let this = rb_self.get();
+        // A bitmap unioned with itself is unchanged.
+        if !std::ptr::eq(this, other) {
+            this.0.write_read(&other.0, |lhs, rhs| {
+                gvl::without_gvl_if_large(lhs._data.len() + rhs._data.len(), || {
+                    lhs._data |= &rhs._data
+                })
+            })?;
+        }
+
+        Ok(*rb_self)
+    }
+
+    /// @yard
+    /// @def intersection!(other)
+    ///
+    /// Intersects this bitmap with another one, in place. Bitwise AND.
+    ///
+    /// @param other [Roaring::Bitmap] The other bitmap to intersect with.
+    ///
+    /// @example Intersecting a bitmap in place.
+    ///     rb1 = Roaring::Bitmap.from_a([1, 2, 3])
+    ///     rb2 = Roaring::Bitmap.from_a([3, 4, 5])
+    ///     rb1.intersection!(rb2)  #=> rb1
+    ///     rb1.to_a                #=> [3]
+    ///
+    /// @return [Roaring::Bitmap] self.
+    fn intersection_inplace(rb_self: Obj<Self>, other: &Self) -> Result<Value, Error> {
+        check_frozen(*rb_self)?;
+        let this = rb_self.get();
+        // A bitmap intersected with itself is unchanged.
+        if !std::ptr::eq(this, other) {
+            this.0.write_read(&other.0, |lhs, rhs| {
+                gvl::without_gvl_if_large(lhs._data.len() + rhs._data.len(), || {
+                    lhs._data &= &rhs._data
+                })
+            })?;
+        }
+
+        Ok(*rb_self)
+    }
+
+    /// @yard
+    /// @def difference!(other)
+    ///
+    /// Removes every item of another bitmap from this one, in place. Bitwise AND NOT.
+    ///
+    /// @param other [Roaring::Bitmap] The other bitmap to compute the difference with.
+    ///
+    /// @example Computing the difference in place.
+    ///     rb1 = Roaring::Bitmap.from_a([1, 2, 3])
+    ///     rb2 = Roaring::Bitmap.from_a([3, 4, 5])
+    ///     rb1.difference!(rb2)    #=> rb1
+    ///     rb1.to_a                #=> [1, 2]
+    ///
+    /// @return [Roaring::Bitmap] self.
+    fn difference_inplace(rb_self: Obj<Self>, other: &Self) -> Result<Value, Error> {
+        check_frozen(*rb_self)?;
+        let this = rb_self.get();
+        if std::ptr::eq(this, other) {
+            this.0.write()?._data.clear();
+        } else {
+            this.0.write_read(&other.0, |lhs, rhs| {
+                gvl::without_gvl_if_large(lhs._data.len() + rhs._data.len(), || {
+                    lhs._data -= &rhs._data
+                })
+            })?;
+        }
+
+        Ok(*rb_self)
+    }
+
+    /// @yard
+    /// @def symmetric_difference!(other)
+    ///
+    /// Computes the symmetric difference with another bitmap, in place. Bitwise XOR.
+    ///
+    /// @param other [Roaring::Bitmap] The other bitmap to compute the symmetric difference with.
+    ///
+    /// @example Computing the symmetric difference in place.
+    ///     rb1 = Roaring::Bitmap.from_a([1, 2, 3])
+    ///     rb2 = Roaring::Bitmap.from_a([3, 4, 5])
+    ///     rb1.symmetric_difference!(rb2)  #=> rb1
+    ///     rb1.to_a                        #=> [1, 2, 4, 5]
+    ///
+    /// @return [Roaring::Bitmap] self.
+    fn symmetric_difference_inplace(rb_self: Obj<Self>, other: &Self) -> Result<Value, Error> {
+        check_frozen(*rb_self)?;
+        let this = rb_self.get();
+        if std::ptr::eq(this, other) {
+            this.0.write()?._data.clear();
+        } else {
+            this.0.write_read(&other.0, |lhs, rhs| {
+                gvl::without_gvl_if_large(lhs._data.len() + rhs._data.len(), || {
+                    lhs._data ^= &rhs._data
+                })
+            })?;
+        }
+
+        Ok(*rb_self)
+    }
+
+    /// Locks every bitmap of the array once and folds them with a multi-way operation.
+    fn aggregate<F>(bitmaps: RArray, op: F) -> Result<B, Error>
+    where
+        F: for<'a> FnOnce(Vec<&'a B>) -> B,
+    {
+        // Work on a private copy, as another thread could remove bitmaps from the caller's array
+        // while the GVL is released. The copy is registered as a GC root until it's dropped, so
+        // it and the bitmaps borrowed from it stay alive: nothing on the Ruby side references
+        // it, and the compiler is free to drop it from the stack the conservative GC scans.
+        let bitmaps = BoxValue::new(bitmaps.funcall::<_, _, RArray>("dup", ())?);
+        let objs = entries::<Obj<Self>>(*bitmaps)?;
+        let locks: Vec<_> = objs.iter().map(|obj| &obj.get().0).collect();
+
+        let d = Lock::read_all(&locks, |wrappers| {
+            let items = wrappers.iter().map(|wrapper| wrapper._data.len()).sum();
+            gvl::without_gvl_if_large(items, || {
+                op(wrappers.into_iter().map(|wrapper| &wrapper._data).collect())
+            })
+        })?;
+        drop(bitmaps);
+
+        Ok(d)
+    }
+
+    /// @yard
+    /// @def union_all(bitmaps)
+    ///
+    /// Unions all the given bitmaps in a single pass. Bitwise OR.
+    ///
+    /// @param bitmaps [Array<Roaring::Bitmap>] The bitmaps to union.
+    ///
+    /// @example Unioning many bitmaps.
+    ///     rb1 = Roaring::Bitmap.from_a([1, 2])
+    ///     rb2 = Roaring::Bitmap.from_a([2, 3])
+    ///     rb3 = Roaring::Bitmap.from_a([5])
+    ///     Roaring::Bitmap.union_all([rb1, rb2, rb3]).to_a    #=> [1, 2, 3, 5]
+    ///
+    /// @return [Roaring::Bitmap] The union of all the bitmaps.
+    fn union_all(class: RClass, bitmaps: RArray) -> Result<Obj<Self>, Error> {
+        let d = Self::aggregate(bitmaps, B::union_all)?;
+        Ok(Self::wrap_as(d, class))
+    }
+
+    /// @yard
+    /// @def union_all_len(bitmaps)
+    ///
+    /// Computes the union of all the given bitmaps and returns the cardinality of the result.
+    ///
+    /// @param bitmaps [Array<Roaring::Bitmap>] The bitmaps to union.
+    ///
+    /// @return [Integer] The cardinality of the union of all the bitmaps.
+    fn union_all_len(bitmaps: RArray) -> Result<u64, Error> {
+        Ok(Self::aggregate(bitmaps, B::union_all)?.len())
+    }
+
+    /// @yard
+    /// @def intersection_all(bitmaps)
+    ///
+    /// Intersects all the given bitmaps in a single pass. Bitwise AND.
+    ///
+    /// @param bitmaps [Array<Roaring::Bitmap>] The bitmaps to intersect.
+    ///
+    /// @example Intersecting many bitmaps.
+    ///     rb1 = Roaring::Bitmap.from_a([1, 2, 3])
+    ///     rb2 = Roaring::Bitmap.from_a([2, 3])
+    ///     rb3 = Roaring::Bitmap.from_a([3, 4])
+    ///     Roaring::Bitmap.intersection_all([rb1, rb2, rb3]).to_a   #=> [3]
+    ///
+    /// @return [Roaring::Bitmap] The intersection of all the bitmaps, empty if none are given.
+    fn intersection_all(class: RClass, bitmaps: RArray) -> Result<Obj<Self>, Error> {
+        let d = Self::aggregate(bitmaps, B::intersection_all)?;
+        Ok(Self::wrap_as(d, class))
+    }
+
+    /// @yard
+    /// @def intersection_all_len(bitmaps)
+    ///
+    /// Computes the intersection of all the given bitmaps and returns the cardinality of the result.
+    ///
+    /// @param bitmaps [Array<Roaring::Bitmap>] The bitmaps to intersect.
+    ///
+    /// @return [Integer] The cardinality of the intersection of all the bitmaps.
+    fn intersection_all_len(bitmaps: RArray) -> Result<u64, Error> {
+        Ok(Self::aggregate(bitmaps, B::intersection_all)?.len())
+    }
+
+    /// @yard
+    /// @def xor_all(bitmaps)
+    ///
+    /// Computes the symmetric difference of all the given bitmaps in a single pass. Bitwise XOR.
+    /// The result holds the items present in an odd number of the bitmaps.
+    ///
+    /// @param bitmaps [Array<Roaring::Bitmap>] The bitmaps to combine.
+    ///
+    /// @example Computing the symmetric difference of many bitmaps.
+    ///     rb1 = Roaring::Bitmap.from_a([1, 2, 3])
+    ///     rb2 = Roaring::Bitmap.from_a([2, 3])
+    ///     rb3 = Roaring::Bitmap.from_a([3, 4])
+    ///     Roaring::Bitmap.xor_all([rb1, rb2, rb3]).to_a    #=> [1, 3, 4]
+    ///
+    /// @return [Roaring::Bitmap] The symmetric difference of all the bitmaps.
+    fn xor_all(class: RClass, bitmaps: RArray) -> Result<Obj<Self>, Error> {
+        let d = Self::aggregate(bitmaps, B::xor_all)?;
+        Ok(Self::wrap_as(d, class))
+    }
+
+    /// @yard
+    /// @def xor_all_len(bitmaps)
+    ///
+    /// Computes the symmetric difference of all the given bitmaps and returns the cardinality of the result.
+    ///
+    /// @param bitmaps [Array<Roaring::Bitmap>] The bitmaps to combine.
+    ///
+    /// @return [Integer] The cardinality of the symmetric difference of all the bitmaps.
+    fn xor_all_len(bitmaps: RArray) -> Result<u64, Error> {
+        Ok(Self::aggregate(bitmaps, B::xor_all)?.len())
     }
 
     /// @yard
     /// @def rank(item)
     ///
-    /// Returns the number of integers that are <= value. rank(u32::MAX) == len(). This is also known as the rank or rank-select idiom.
+    /// Returns the number of integers that are <= value. rank of the largest integer == len(). This is also known as the rank or rank-select idiom.
     ///
     /// @example Computing the rank of an item in a bitmap.
     ///     rb = Roaring::Bitmap.new
@@ -586,18 +1404,25 @@ impl MutWrapper {
     ///     rb.rank(3) #=> 3
     ///
     /// @return [Integer] The number of integers that are <= value.
-    /// @raise [Roaring::Error] If the item is greater than u32::MAX.
+    /// @raise [RangeError] If the item is too large for the bitmap.
     /// @raise [Roaring::Error] If the item is less than 0.
-    fn rank(&self, item: u32) -> Result<u64, Error> {
-        Ok(self.0.borrow()._data.rank(item))
+    fn rank(&self, item: B::Value) -> Result<u64, Error> {
+        Ok(self.0.read()?._data.rank(item))
     }
 
     /// @yard
     ///
-    /// @overload each(&block)
+    /// @overload each(snapshot: false, &block)
     ///
     ///     Iterates over the bitmap and yields each item.
     ///
+    ///     Modifying the bitmap while it is being iterated raises a Roaring::ConcurrentModificationError,
+    ///     whether from the block itself or from another thread. Pass `snapshot: true` to iterate over a
+    ///     copy of the bitmap taken when iteration starts instead, which allows modifications at the cost
+    ///     of copying the bitmap. Roaring::ConcurrentBitmap always iterates over a snapshot.
+    ///
+    ///     @param snapshot [Boolean] Whether to iterate over a copy of the bitmap.
+    ///
     ///     @example Iterating over a bitmap.
     ///         rb = Roaring::Bitmap.new
     ///         rb.insert_many([1, 2, 3, 4, 5])
@@ -605,40 +1430,478 @@ impl MutWrapper {
     ///             puts i
     ///         end
     ///
+    ///     @example Removing items while iterating.
+    ///         rb = Roaring::Bitmap.from_a([1, 2, 3, 4, 5])
+    ///         rb.each { |i| rb.remove(i) if i.odd? }                  #=> raises Roaring::ConcurrentModificationError
+    ///         rb.each(snapshot: true) { |i| rb.remove(i) if i.odd? }
+    ///         rb.to_a                                                 #=> [2, 4]
+    ///
     ///     @yield [Integer] The item in the bitmap.
     ///
     ///     @return [Roaring::Bitmap] The bitmap.
+    ///     @raise [Roaring::ConcurrentModificationError] If the bitmap is modified during iteration.
     ///
-    /// @overload each
+    /// @overload each(snapshot: false)
     ///
-    ///     Returns an enumerator if no block is given.
+    ///     Returns an enumerator if no block is given, whose `size` is the cardinality of the
+    ///     bitmap. With `snapshot: true`, the snapshot is taken when the enumerator is created.
     ///
     ///     @example Receiving an Enumerator if no block is given.
     ///         rb = Roaring::Bitmap.new
     ///         rb.insert_many([1, 2, 3, 4, 5])
-    ///         rb.each #=> #<Enumerator: ...>
+    ///         rb.each         #=> #<Enumerator: ...>
+    ///         rb.each.size    #=> 5
     ///
     ///     @return [Enumerator] An enumerator.
-    fn each(rb_self: Obj<Self>) -> Result<Value, Error> {
+    fn each(rb_self: Obj<Self>, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(), (), (), (), RHash, ()>(args)?;
+        let kwargs = get_kwargs::<_, (), (Option<bool>,), ()>(args.keywords, &[], &["snapshot"])?;
+        let snapshot =
+            kwargs.optional.0.unwrap_or(false) || rb_self.is_kind_of(concurrent_bitmap_class());
+        let self_struct = rb_self.get();
+
         if block_given() {
-            let self_struct = rb_self.get();
-            let data = &self_struct.0.borrow()._data;
-            let block = block_proc().unwrap();
-            for i in data.iter() {
-                let rparams = RArray::with_capacity(1);
-                rparams.push(i).unwrap();
-                match block.call::<RArray, Option<Value>>(rparams) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        return Err(e);
-                    }
-                }
+            if snapshot {
+                // Iterate over a copy, so that other threads (or the block itself) can modify
+                // the bitmap in the meantime.
+                let data = self_struct.0.read()?._data.clone();
+                yield_all(data.iter())?;
+            } else {
+                let _iterating = self_struct.0.iterate();
+                let data = self_struct.0.read()?;
+                yield_all(data._data.iter())?;
             }
 
             Ok(*rb_self)
+        } else if snapshot {
+            let data = self_struct.0.read()?._data.clone();
+            let copy = Obj::wrap(Self::new(data));
+            Ok(enumerator_with_size(*copy, "each"))
+        } else {
+            Ok(enumerator_with_size(*rb_self, "each"))
+        }
+    }
+
+    /// Yields the integers of the bitmap within `range`, in descending order if `reverse`. Like
+    /// `each`, Roaring::ConcurrentBitmap iterates over a snapshot.
+    fn yield_in(
+        rb_self: Obj<Self>,
+        range: RangeInclusive<B::Value>,
+        reverse: bool,
+    ) -> Result<Value, Error> {
+        let self_struct = rb_self.get();
+        let yield_range = |data: &B| {
+            let iter = data.iter_in(range.clone());
+            if reverse {
+                yield_all(iter.rev())
+            } else {
+                yield_all(iter)
+            }
+        };
+
+        if rb_self.is_kind_of(concurrent_bitmap_class()) {
+            let data = self_struct.0.read()?._data.clone();
+            yield_range(&data)?;
         } else {
-            Ok(*rb_self.enumeratorize("each", ()))
+            let _iterating = self_struct.0.iterate();
+            let data = self_struct.0.read()?;
+            yield_range(&data._data)?;
+        }
+
+        Ok(*rb_self)
+    }
+
+    /// @yard
+    ///
+    /// @overload reverse_each(&block)
+    ///
+    ///     Iterates over the integers of the bitmap in descending order, from the largest one.
+    ///
+    ///     @example Iterating from the largest integer.
+    ///         rb = Roaring::Bitmap.from_a([1, 5, 9])
+    ///         rb.reverse_each.to_a     #=> [9, 5, 1]
+    ///
+    ///     @yield [Integer] The item in the bitmap.
+    ///
+    ///     @return [Roaring::Bitmap] The bitmap.
+    ///     @raise [Roaring::ConcurrentModificationError] If the bitmap is modified during iteration.
+    ///
+    /// @overload reverse_each
+    ///
+    ///     Returns an enumerator if no block is given, whose `size` is the cardinality of the
+    ///     bitmap.
+    ///
+    ///     @return [Enumerator] An enumerator.
+    fn reverse_each(rb_self: Obj<Self>) -> Result<Value, Error> {
+        if !block_given() {
+            return Ok(enumerator_with_size(*rb_self, "reverse_each"));
+        }
+        Self::yield_in(rb_self, B::Value::default()..=B::MAX, true)
+    }
+
+    /// @yard
+    ///
+    /// @overload each_from(start, &block)
+    ///
+    ///     Iterates in ascending order over the integers of the bitmap greater than or equal to
+    ///     `start`, skipping the containers below it instead of scanning them. Resumes a cursor.
+    ///
+    ///     @param start [Integer] The smallest integer to yield.
+    ///
+    ///     @example Resuming after the last integer seen.
+    ///         rb = Roaring::Bitmap.from_a([1, 5, 9, 12])
+    ///         rb.each_from(5 + 1).to_a     #=> [9, 12]
+    ///
+    ///     @yield [Integer] The item in the bitmap.
+    ///
+    ///     @return [Roaring::Bitmap] The bitmap.
+    ///     @raise [Roaring::ConcurrentModificationError] If the bitmap is modified during iteration.
+    ///
+    /// @overload each_from(start)
+    ///
+    ///     Returns an enumerator if no block is given.
+    ///
+    ///     @return [Enumerator] An enumerator.
+    fn each_from(rb_self: Obj<Self>, start: B::Value) -> Result<Value, Error> {
+        if !block_given() {
+            return Ok(*rb_self.enumeratorize("each_from", (start,)));
+        }
+        Self::yield_in(rb_self, start..=B::MAX, false)
+    }
+
+    /// @yard
+    ///
+    /// @overload each_before(value, &block)
+    ///
+    ///     Iterates in descending order over the integers of the bitmap less than `value`,
+    ///     skipping the containers above it instead of scanning them. Pages through a bitmap
+    ///     newest first.
+    ///
+    ///     @param value [Integer] The integer to stop before.
+    ///
+    ///     @example Reading the next page, newest first.
+    ///         rb = Roaring::Bitmap.from_a([1, 5, 9, 12])
+    ///         rb.each_before(12).first(2)     #=> [9, 5]
+    ///
+    ///     @yield [Integer] The item in the bitmap.
+    ///
+    ///     @return [Roaring::Bitmap] The bitmap.
+    ///     @raise [Roaring::ConcurrentModificationError] If the bitmap is modified during iteration.
+    ///
+    /// @overload each_before(value)
+    ///
+    ///     Returns an enumerator if no block is given.
+    ///
+    ///     @return [Enumerator] An enumerator.
+    fn each_before(rb_self: Obj<Self>, value: B::Value) -> Result<Value, Error> {
+        if !block_given() {
+            return Ok(*rb_self.enumeratorize("each_before", (value,)));
+        }
+        let value: u64 = value.into();
+        match value
+            .checked_sub(1)
+            .and_then(|end| B::Value::try_from(end).ok())
+        {
+            Some(end) => Self::yield_in(rb_self, B::Value::default()..=end, true),
+            None => Ok(*rb_self),
+        }
+    }
+
+    /// @yard
+    ///
+    /// @overload each_in(range, &block)
+    ///
+    ///     Iterates in ascending order over the integers of the bitmap within `range`, skipping
+    ///     the containers outside it instead of scanning them. Beginless and endless ranges are
+    ///     allowed.
+    ///
+    ///     @param range [Range] The range of integers to yield.
+    ///
+    ///     @example Iterating over a range.
+    ///         rb = Roaring::Bitmap.from_a([1, 5, 9, 12])
+    ///         rb.each_in(2...12).to_a     #=> [5, 9]
+    ///
+    ///     @yield [Integer] The item in the bitmap.
+    ///
+    ///     @return [Roaring::Bitmap] The bitmap.
+    ///     @raise [Roaring::ConcurrentModificationError] If the bitmap is modified during iteration.
+    ///
+    /// @overload each_in(range)
+    ///
+    ///     Returns an enumerator if no block is given.
+    ///
+    ///     @return [Enumerator] An enumerator.
+    fn each_in(rb_self: Obj<Self>, range: Range) -> Result<Value, Error> {
+        if !block_given() {
+            return Ok(*rb_self.enumeratorize("each_in", (range,)));
         }
+        match range_to_inclusive::<B>(range)? {
+            Some(range) => Self::yield_in(rb_self, range, false),
+            None => Ok(*rb_self),
+        }
+    }
+
+    /// @yard
+    /// @def page(after: nil, limit:)
+    ///
+    /// Reads a page of up to `limit` integers in ascending order, starting after the page whose
+    /// cursor is `after`, without materializing the bitmap. Returns the page with the cursor to
+    /// pass as `after:` for the next page, which is nil once the last page is read.
+    ///
+    /// Cursors are opaque URL-safe strings, and stay valid while the bitmap changes: the next
+    /// page starts after the last integer of the previous one, whether it's still in the bitmap
+    /// or not.
+    ///
+    /// @param after [String, nil] The cursor returned with the previous page, or nil for the
+    ///     first page.
+    /// @param limit [Integer] The largest number of integers in the page.
+    ///
+    /// @example Paging through a bitmap.
+    ///     rb = Roaring::Bitmap.from_a([1, 5, 9, 12, 20])
+    ///     ids, cursor = rb.page(limit: 2)                    #=> [[1, 5], "AQAAAAAAAAAF"]
+    ///     ids, cursor = rb.page(after: cursor, limit: 2)     #=> [[9, 12], "AQAAAAAAAAAM"]
+    ///     ids, cursor = rb.page(after: cursor, limit: 2)     #=> [[20], nil]
+    ///
+    /// @return [Array(Array<Integer>, String)] The integers of the page and the next cursor.
+    /// @raise [ArgumentError] If `limit` is not positive, or `after` isn't a cursor returned by
+    ///     #page.
+    fn page(&self, args: &[Value]) -> Result<RArray, Error> {
+        let (after, limit) = page_options(args)?;
+        let start = after.map_or(Some(0), |after| after.checked_add(1));
+        let Some(start) = start.and_then(|start| B::Value::try_from(start).ok()) else {
+            return page_result(RArray::new(), None::<String>);
+        };
+        let data = self.0.read()?;
+        let (values, next) = take_page(data._data.iter_in(start..=B::MAX), limit)?;
+        page_result(values, next.map(|last| cursor::encode(last.into())))
+    }
+
+    /// @yard
+    /// @def page_by_rank(offset, limit)
+    ///
+    /// Reads a page of up to `limit` integers in ascending order, starting with the integer of
+    /// rank `offset` (0-based, as in #nth), without materializing the bitmap. Returns the page
+    /// with the offset of the next page, which is nil once the last page is read.
+    ///
+    /// Finding the integer of rank `offset` adds up the cardinalities of the containers before
+    /// it, one container at a time (a run container counts its runs), so a page costs
+    /// O(containers before `offset` + `limit`) rather than O(log n + `limit`). Prefer #page when
+    /// pages are read in order.
+    ///
+    /// @param offset [Integer] The number of integers to skip.
+    /// @param limit [Integer] The largest number of integers in the page.
+    ///
+    /// @example Paging through a bitmap by offset.
+    ///     rb = Roaring::Bitmap.from_a([1, 5, 9, 12, 20])
+    ///     rb.page_by_rank(0, 2)     #=> [[1, 5], 2]
+    ///     rb.page_by_rank(4, 2)     #=> [[20], nil]
+    ///     rb.page_by_rank(5, 2)     #=> [[], nil]
+    ///
+    /// @return [Array(Array<Integer>, Integer)] The integers of the page and the next offset.
+    /// @raise [ArgumentError] If `limit` is not positive.
+    fn page_by_rank(&self, offset: u64, limit: usize) -> Result<RArray, Error> {
+        let limit = page_limit(limit)?;
+        let data = self.0.read()?;
+        let Some(start) = data._data.select(offset) else {
+            return page_result(RArray::new(), None::<u64>);
+        };
+        let (values, next) = take_page(data._data.iter_in(start..=B::MAX), limit)?;
+        page_result(values, next.map(|_| offset + limit as u64))
+    }
+
+    /// @yard
+    /// @def next_value(value)
+    ///
+    /// Finds the smallest integer of the bitmap greater than or equal to `value`, seeking to the
+    /// container of `value` instead of scanning the bitmap.
+    ///
+    /// @param value [Integer] The integer to start from.
+    ///
+    /// @example Finding the next integer.
+    ///     rb = Roaring::Bitmap.from_a([3, 4, 5, 10])
+    ///     rb.next_value(4)      #=> 4
+    ///     rb.next_value(6)      #=> 10
+    ///     rb.next_value(11)     #=> nil
+    ///
+    /// @return [Integer, nil] The integer, or nil if there is none.
+    fn next_value(&self, value: B::Value) -> Result<Option<B::Value>, Error> {
+        let data = self.0.read()?;
+        Ok(data._data.iter_in(value..=B::MAX).next())
+    }
+
+    /// @yard
+    /// @def prev_value(value)
+    ///
+    /// Finds the largest integer of the bitmap less than or equal to `value`, seeking to the
+    /// container of `value` instead of scanning the bitmap.
+    ///
+    /// @param value [Integer] The integer to start from.
+    ///
+    /// @example Finding the previous integer.
+    ///     rb = Roaring::Bitmap.from_a([3, 4, 5, 10])
+    ///     rb.prev_value(4)     #=> 4
+    ///     rb.prev_value(9)     #=> 5
+    ///     rb.prev_value(2)     #=> nil
+    ///
+    /// @return [Integer, nil] The integer, or nil if there is none.
+    fn prev_value(&self, value: B::Value) -> Result<Option<B::Value>, Error> {
+        let data = self.0.read()?;
+        Ok(data._data.iter_in(B::Value::default()..=value).next_back())
+    }
+
+    /// @yard
+    /// @def next_absent(value)
+    ///
+    /// Finds the smallest integer greater than or equal to `value` that is not in the bitmap,
+    /// skipping a whole run of consecutive integers at once. Finds the first free slot.
+    ///
+    /// @param value [Integer] The integer to start from.
+    ///
+    /// @example Finding the next free slot.
+    ///     rb = Roaring::Bitmap.from_a([3, 4, 5, 10])
+    ///     rb.next_absent(2)     #=> 2
+    ///     rb.next_absent(3)     #=> 6
+    ///
+    /// @return [Integer, nil] The integer, or nil if every integer from `value` up to
+    ///     2**32 - 1 is in the bitmap.
+    fn next_absent(&self, value: B::Value) -> Result<Option<B::Value>, Error> {
+        Ok(self.0.read()?._data.next_absent(value))
+    }
+
+    /// @yard
+    /// @def prev_absent(value)
+    ///
+    /// Finds the largest integer less than or equal to `value` that is not in the bitmap,
+    /// skipping a whole run of consecutive integers at once.
+    ///
+    /// @param value [Integer] The integer to start from.
+    ///
+    /// @example Finding the previous free slot.
+    ///     rb = Roaring::Bitmap.from_a([3, 4, 5, 10])
+    ///     rb.prev_absent(6)     #=> 6
+    ///     rb.prev_absent(5)     #=> 2
+    ///
+    /// @return [Integer, nil] The integer, or nil if every integer from 0 up to `value` is in
+    ///     the bitmap.
+    fn prev_absent(&self, value: B::Value) -> Result<Option<B::Value>, Error> {
+        Ok(self.0.read()?._data.prev_absent(value))
+    }
+
+    /// @yard
+    /// @def nearest(value)
+    ///
+    /// Finds the integer of the bitmap closest to `value`, the smaller one on a tie.
+    ///
+    /// @param value [Integer] The integer to look around.
+    ///
+    /// @example Finding the closest integer.
+    ///     rb = Roaring::Bitmap.from_a([3, 4, 5, 10])
+    ///     rb.nearest(7)     #=> 5
+    ///     rb.nearest(8)     #=> 10
+    ///     rb.nearest(4)     #=> 4
+    ///
+    /// @return [Integer, nil] The integer, or nil if the bitmap is empty.
+    fn nearest(&self, value: B::Value) -> Result<Option<B::Value>, Error> {
+        let data = self.0.read()?;
+        let next = data._data.iter_in(value..=B::MAX).next();
+        let prev = data
+            ._data
+            .iter_in(B::Value::default()..=value)
+            .rev()
+            .find(|&prev| prev < value);
+        Ok(match (prev, next) {
+            (Some(prev), Some(next)) if value - prev <= next - value => Some(prev),
+            (prev, None) => prev,
+            (_, next) => next,
+        })
+    }
+
+    /// @yard
+    ///
+    /// @overload each_range(pairs: false, &block)
+    ///
+    ///     Iterates over the runs of consecutive integers of the bitmap, in ascending order. Runs
+    ///     are read straight from the containers, so a bitmap of a few long runs is walked in
+    ///     O(runs) rather than O(cardinality). Like `each`, Roaring::ConcurrentBitmap iterates
+    ///     over a snapshot.
+    ///
+    ///     @param pairs [Boolean] Yields `[start, end]` Arrays instead of Ranges.
+    ///
+    ///     @example Iterating over the runs of a bitmap.
+    ///         rb = Roaring::Bitmap.from_a([1, 2, 3, 7, 10, 11])
+    ///         rb.each_range.to_a                  #=> [1..3, 7..7, 10..11]
+    ///         rb.each_range(pairs: true).to_a     #=> [[1, 3], [7, 7], [10, 11]]
+    ///
+    ///     @yield [Range<Integer>, Array<Integer>] The inclusive run, or its first and last integers.
+    ///
+    ///     @return [Roaring::Bitmap] The bitmap.
+    ///     @raise [Roaring::ConcurrentModificationError] If the bitmap is modified during iteration.
+    ///
+    /// @overload each_range(pairs: false)
+    ///
+    ///     Returns an enumerator if no block is given.
+    ///
+    ///     @return [Enumerator] An enumerator.
+    fn each_range(rb_self: Obj<Self>, args: &[Value]) -> Result<Value, Error> {
+        let pairs = pairs_option(args)?;
+
+        if block_given() {
+            let block = block_proc()?;
+            Self::read_for_callbacks(rb_self, |data| yield_ranges(&block, data.ranges(), pairs))?;
+
+            Ok(*rb_self)
+        } else if pairs {
+            // The enumerator can't pass keywords back, so it iterates over the collected pairs.
+            let data = rb_self.get().0.read()?;
+            Ok(*ranges_array(data._data.ranges(), true)?.enumeratorize("each", ()))
+        } else {
+            Ok(*rb_self.enumeratorize("each_range", ()))
+        }
+    }
+
+    /// @yard
+    /// @def to_ranges(pairs: false)
+    ///
+    /// Returns the runs of consecutive integers of the bitmap, in ascending order.
+    ///
+    /// @param pairs [Boolean] Returns `[start, end]` Arrays instead of Ranges.
+    ///
+    /// @example Converting a bitmap to SQL conditions.
+    ///     rb = Roaring::Bitmap.from_a([1, 2, 3, 7])
+    ///     rb.to_ranges                        #=> [1..3, 7..7]
+    ///     rb.to_ranges(pairs: true).map { |a, b| "id BETWEEN #{a} AND #{b}" }.join(" OR ")
+    ///     #=> "id BETWEEN 1 AND 3 OR id BETWEEN 7 AND 7"
+    ///
+    /// @return [Array<Range<Integer>>, Array<Array<Integer>>] The runs of the bitmap.
+    fn to_ranges(&self, args: &[Value]) -> Result<RArray, Error> {
+        let pairs = pairs_option(args)?;
+        let data = self.0.read()?;
+        ranges_array(data._data.ranges(), pairs)
+    }
+
+    /// @yard
+    /// @def from_ranges(ranges)
+    ///
+    /// Initializes a new bitmap from runs of integers, inserting each run as a whole.
+    ///
+    /// @param ranges [Array<Range<Integer>, Array<Integer>>] Ranges, or `[start, end]` pairs of
+    ///     inclusive bounds, as returned by #to_ranges.
+    ///
+    /// @example Initialize a bitmap from ranges.
+    ///     rb = Roaring::Bitmap.from_ranges([1..3, [7, 7], 10...12])
+    ///     rb.to_a     #=> [1, 2, 3, 7, 10, 11]
+    ///
+    /// @return [Roaring::Bitmap] A new bitmap holding the runs.
+    /// @raise [TypeError] If an element is neither a Range nor a pair.
+    /// @raise [RangeError] If a run is out of bounds.
+    fn from_ranges(class: RClass, ranges: RArray) -> Result<Obj<Self>, Error> {
+        let mut data = B::default();
+        for element in entries::<Value>(ranges)? {
+            if let Some(r) = range_to_inclusive::<B>(range_from_element(element)?)? {
+                data.insert_range(r)?;
+            }
+        }
+        Ok(Self::wrap_as(data, class))
     }
 
     /// @yard
@@ -653,7 +1916,30 @@ impl MutWrapper {
     ///
     /// @return [Integer] The size of the bitmap in bytes.
     fn byte_size(&self) -> Result<usize, Error> {
-        Ok(self.0.borrow()._data.serialized_size())
+        Ok(self.0.read()?._data.serialized_size())
+    }
+
+    /// @yard
+    ///
+    /// Describes the bitmap with its cardinality, the range of its values, its serialized size in
+    /// bytes and its first ten runs of consecutive integers, without listing every integer.
+    ///
+    /// @example Inspecting a bitmap.
+    ///     rb = Roaring::Bitmap.from_a([1, 2, 3, 7, 9, 10])
+    ///     rb.inspect
+    ///     #=> "#<Roaring::Bitmap cardinality=6 range=1..10 bytes=28 [1-3, 7, 9-10]>"
+    ///
+    /// @return [String] The description.
+    fn inspect(rb_self: Obj<Self>) -> Result<String, Error> {
+        let data = rb_self.get().0.read()?;
+        let data = &data._data;
+        Ok(interval::inspect(
+            &unsafe { rb_self.classname() },
+            data.len(),
+            data.min().zip(data.max()),
+            data.serialized_size(),
+            data.ranges(),
+        ))
     }
 
     /// @yard
@@ -672,7 +1958,8 @@ impl MutWrapper {
     ///
     /// @return [Boolean] True if the bitmap is equal to another bitmap, false otherwise.
     fn eql(&self, other: &Self) -> Result<bool, Error> {
-        Ok(self.0.borrow()._data == other.0.borrow()._data)
+        self.0
+            .read_both(&other.0, |lhs, rhs| lhs._data == rhs._data)
     }
 
     /// @yard
@@ -685,10 +1972,35 @@ impl MutWrapper {
     ///     rb.insert_many([1, 2, 3])
     ///     rb.serialize    #=> ":0\x00\x00\x01\x00\x00\x00\x00\x00\x04\x00\x10\x00\x00\x00\x01\x00\x02\x00\x03\x00\x04\x00\x05\x00"
     ///
+    /// @example Serializing a bitmap with compression.
+    ///     rb = Roaring::Bitmap.from_a((0...1_000_000).step(7).to_a)
+    ///     data = rb.serialize(compression: :zstd, level: 19)
+    ///     data.bytesize < rb.serialize.bytesize                   #=> true
+    ///     Roaring::Bitmap.deserialize(data).eql?(rb)              #=> true
+    ///
+    /// @example Serializing a bitmap with a checksum.
+    ///     data = Roaring::Bitmap.from_a([1, 2, 3]).serialize(checksum: true)
+    ///     data[-1] = "\xFF"
+    ///     Roaring::Bitmap.deserialize(data)   # raises Roaring::ChecksumError
+    ///
+    /// @param compression [Symbol, nil] Compresses the data with :zstd or :lz4, inside an envelope
+    ///     that #deserialize detects. Without compression, the plain portable format is written.
+    /// @param level [Integer, nil] The zstd compression level, 3 by default.
+    /// @param checksum [Boolean] Records the cardinality and a CRC32C of the data in the envelope,
+    ///     which #deserialize verifies.
+    ///
     /// @return [String] The binary representation of the bitmap.
-    fn serialize(&self) -> Result<RString, Error> {
-        let mut buf = vec![];
-        self.0.borrow()._data.serialize_into(&mut buf).unwrap();
+    /// @raise [ArgumentError] If the compression options are invalid.
+    /// @raise [Roaring::SerializationError] If the bitmap could not be written.
+    fn serialize(&self, args: &[Value]) -> Result<RString, Error> {
+        let (compression, checksum) = serialize_options(args)?;
+        let data = self.0.read()?;
+        let buf = gvl::without_gvl_if_large(data._data.len(), || {
+            let mut buf = Vec::with_capacity(data._data.serialized_size());
+            data._data.serialize_into(&mut buf)?;
+            envelope::seal(buf, data._data.len(), compression, checksum)
+        })
+        .map_err(|e| Error::new(errors::serialization_error(), e.to_string()))?;
         Ok(RString::from_slice(&buf))
     }
 
@@ -707,80 +2019,444 @@ impl MutWrapper {
     ///     from_binary = Roaring::Bitmap.deserialize(dumped)
     ///     rb.eql?(from_binary)    #=> true
     ///
+    /// Data written by `serialize(compression: ...)` is detected and decompressed, and data
+    /// written by `serialize(checksum: true)` is verified.
+    ///
     /// @return [Roaring::Bitmap] The bitmap.
-    fn deserialize(rstr: RString) -> Result<Self, Error> {
-        let buf = unsafe { rstr.as_slice() };
-        let d = RoaringBitmap::deserialize_from(&mut &buf[..]).unwrap();
-        Ok(Self(RefCell::new(Wrapper { _data: d })))
+    /// @raise [Roaring::ChecksumError] If the data doesn't match its checksum.
+    /// @raise [Roaring::DeserializationError] If the string is not a valid serialized bitmap.
+    fn deserialize(class: RClass, rstr: RString) -> Result<Obj<Self>, Error> {
+        let decode = |buf: &[u8]| {
+            let opened = envelope::open(buf, B::MAX_LEN)?;
+            let d = B::deserialize(&opened.data)?;
+            opened.verify_len(d.len()).map(|_| d)
+        };
+        // Another thread could modify the string while the GVL is released, so only frozen
+        // strings are decoded in place.
+        let d = if rstr.is_frozen() {
+            let buf = unsafe { rstr.as_slice() };
+            gvl::without_gvl_if_large(buf.len() as u64, || decode(buf))
+        } else {
+            let buf = unsafe { rstr.as_slice() }.to_vec();
+            gvl::without_gvl_if_large(buf.len() as u64, || decode(&buf))
+        }
+        .map_err(|e| errors::invalid_data(&e))?;
+        Ok(Self::wrap_as(d, class))
     }
-}
 
-#[magnus::init]
-fn init() -> Result<(), Error> {
-    let module = define_module("Roaring")?;
-    let bitmap_class = module.define_class("Bitmap", Default::default())?;
-    bitmap_class.define_singleton_method("new", function!(MutWrapper::new, 0))?;
-    bitmap_class.define_singleton_method("full", function!(MutWrapper::new_full, 0))?;
-    bitmap_class.define_singleton_method("from_a", function!(MutWrapper::from_array, 1))?;
-
-    bitmap_class.define_method("insert", method!(MutWrapper::insert, 1))?;
-
-    bitmap_class.define_method("insert_many", method!(MutWrapper::insert_many, 1))?;
-
-    bitmap_class.define_method("remove", method!(MutWrapper::remove, 1))?;
+    /// @yard
+    /// @def serialize_to(io)
+    ///
+    /// Serializes the bitmap into an IO-like object, in the same format as #serialize, without
+    /// building the whole serialized String in memory. `io` can be any object responding to
+    /// `write`, such as a File, Socket, StringIO or Zlib::GzipWriter; it is written to in chunks.
+    ///
+    /// The bitmap can't be modified while it is being written, except for a
+    /// Roaring::ConcurrentBitmap, which writes a snapshot.
+    ///
+    /// @param io [IO] The object to write to.
+    ///
+    /// @example Writing a bitmap to a file.
+    ///     rb = Roaring::Bitmap.from_a([1, 2, 3])
+    ///     File.open("bitmap.bin", "wb") { |file| rb.serialize_to(file) }
+    ///
+    /// @return [Integer] The number of bytes written.
+    /// @raise [Roaring::SerializationError] If the bitmap can't be serialized.
+    fn serialize_to(rb_self: Obj<Self>, io: Value) -> Result<u64, Error> {
+        // `write` runs arbitrary Ruby code, which must not be able to modify the bitmap while
+        // it is read-locked.
+        Self::read_for_callbacks(rb_self, |data| {
+            let mut writer = BufWriter::with_capacity(ruby_io::BUFFER_SIZE, RubyWriter::new(io));
+            let result = data
+                .serialize_into(&mut writer)
+                .and_then(|_| writer.flush());
+            let (mut writer, _) = writer.into_parts();
+            result.map_err(|e| {
+                writer
+                    .take_raised()
+                    .unwrap_or_else(|| Error::new(errors::serialization_error(), e.to_string()))
+            })?;
+
+            Ok(writer.written())
+        })
+    }
 
-    bitmap_class.define_method("contains", method!(MutWrapper::contains, 1))?;
+    /// @yard
+    /// @def deserialize_from(io)
+    ///
+    /// Deserializes a bitmap from an IO-like object, reading only the bytes of the bitmap.
+    /// `io` can be any object responding to `read(length)`, such as a File, Socket, StringIO or
+    /// Zlib::GzipReader.
+    ///
+    /// @param io [IO] The object to read from.
+    ///
+    /// @example Reading a bitmap from a file.
+    ///     rb = File.open("bitmap.bin", "rb") { |file| Roaring::Bitmap.deserialize_from(file) }
+    ///
+    /// @return [Roaring::Bitmap] The bitmap.
+    /// @raise [Roaring::ChecksumError] If the data doesn't match its checksum.
+    /// @raise [Roaring::DeserializationError] If the data is not a valid serialized bitmap.
+    fn deserialize_from(class: RClass, io: Value) -> Result<Obj<Self>, Error> {
+        let mut reader = RubyReader::new(io);
+        let mut prefix = [0; 4];
+        let d = reader
+            .read_exact(&mut prefix)
+            .and_then(|_| {
+                if envelope::is_enveloped(&prefix) {
+                    let opened = envelope::read(&prefix, &mut reader, B::MAX_LEN)?;
+                    let d = B::deserialize(&opened.data)?;
+                    opened.verify_len(d.len()).map(|_| d)
+                } else {
+                    B::deserialize_from(Cursor::new(prefix).chain(&mut reader))
+                }
+            })
+            .map_err(|e| {
+                reader
+                    .take_raised()
+                    .unwrap_or_else(|| errors::invalid_data(&e))
+            })?;
+        Ok(Self::wrap_as(d, class))
+    }
 
-    bitmap_class.define_method("to_a", method!(MutWrapper::to_vec, 0))?;
+    /// @yard
+    /// @def to_clickhouse
+    ///
+    /// Serializes the bitmap into the binary format of a ClickHouse `groupBitmap` aggregate
+    /// state of UInt32 values, as stored in an `AggregateFunction(groupBitmap, UInt32)` column and
+    /// read by `groupBitmapMerge`. Bitmaps of up to 32 values are written as a small set, like
+    /// ClickHouse does.
+    ///
+    /// @example Inserting a bitmap into ClickHouse.
+    ///     rb = Roaring::Bitmap.from_a([1, 2, 3])
+    ///     rb.to_clickhouse    #=> "\x00\x03\x01\x00\x00\x00\x02\x00\x00\x00\x03\x00\x00\x00"
+    ///
+    /// @return [String] The aggregate state.
+    /// @raise [Roaring::SerializationError] If the bitmap could not be written.
+    fn to_clickhouse(&self) -> Result<RString, Error> {
+        let data = self.0.read()?;
+        let buf = gvl::without_gvl_if_large(data._data.len(), || data._data.to_clickhouse())
+            .map_err(|e| Error::new(errors::serialization_error(), e.to_string()))?;
+        Ok(RString::from_slice(&buf))
+    }
 
-    bitmap_class.define_method("clear", method!(MutWrapper::clear, 0))?;
+    /// @yard
+    /// @def from_clickhouse(rstr)
+    ///
+    /// Deserializes a bitmap from a ClickHouse `groupBitmap` aggregate state of UInt32 values, as
+    /// returned by `groupBitmapState` or `bitmapBuild`, in either its small set or roaring
+    /// representation.
+    ///
+    /// @param rstr [String] The aggregate state.
+    ///
+    /// @example Reading a bitmap from ClickHouse.
+    ///     # state = SELECT groupBitmapState(user_id) FROM events
+    ///     rb = Roaring::Bitmap.from_clickhouse(state)
+    ///
+    /// @return [Roaring::Bitmap] The bitmap.
+    /// @raise [Roaring::DeserializationError] If the string is not a valid aggregate state.
+    fn from_clickhouse(class: RClass, rstr: RString) -> Result<Obj<Self>, Error> {
+        // Another thread could modify the string while the GVL is released, so only frozen
+        // strings are decoded in place.
+        let d = if rstr.is_frozen() {
+            let buf = unsafe { rstr.as_slice() };
+            gvl::without_gvl_if_large(buf.len() as u64, || B::from_clickhouse(buf))
+        } else {
+            let buf = unsafe { rstr.as_slice() }.to_vec();
+            gvl::without_gvl_if_large(buf.len() as u64, || B::from_clickhouse(&buf))
+        }
+        .map_err(|e| errors::invalid_data(&e))?;
+        Ok(Self::wrap_as(d, class))
+    }
 
-    bitmap_class.define_method("cardinality", method!(MutWrapper::len, 0))?;
+    /// @yard
+    /// @def parse(string)
+    ///
+    /// Parses an interval string, such as `"1-5,7,9-12"`: comma-separated integers and
+    /// inclusive ranges of integers, in any order. Whitespace around integers and separators is
+    /// ignored, and an empty string is an empty bitmap.
+    ///
+    /// @param string [String] The interval string.
+    ///
+    /// @example Parsing an interval string.
+    ///     rb = Roaring::Bitmap.parse("1-5, 7, 9-12")
+    ///     rb.to_a     #=> [1, 2, 3, 4, 5, 7, 9, 10, 11, 12]
+    ///
+    /// @example Handling malformed input.
+    ///     Roaring::Bitmap.parse("1-5,x")
+    ///     #=> raises Roaring::ParseError, "expected an integer, found 'x' at offset 4"
+    ///
+    /// @return [Roaring::Bitmap] The bitmap.
+    /// @raise [Roaring::ParseError] If the string is malformed, with the byte offset of the
+    ///     problem in `offset`.
+    fn parse(class: RClass, string: RString) -> Result<Obj<Self>, Error> {
+        let string = string.to_string()?;
+        let mut data = B::default();
+        let mut inserted = Ok(0);
+        interval::parse::<B::Value>(&string, |range| {
+            if inserted.is_ok() {
+                inserted = data.insert_range(range);
+            }
+        })
+        .map_err(errors::parse_failed)?;
+        inserted?;
+        Ok(Self::wrap_as(data, class))
+    }
 
-    bitmap_class.define_method("empty?", method!(MutWrapper::is_empty, 0))?;
+    /// @yard
+    ///
+    /// @overload to_interval_string
+    ///
+    ///     Formats the bitmap as an interval string, such as `"1-5,7,9-12"`, that ::parse reads
+    ///     back. Runs are read straight from the containers, one at a time.
+    ///
+    ///     @example Formatting a bitmap.
+    ///         Roaring::Bitmap.from_a([1, 2, 3, 4, 5, 7, 9, 10]).to_interval_string     #=> "1-5,7,9-10"
+    ///
+    ///     @return [String] The interval string.
+    ///
+    /// @overload to_interval_string(io)
+    ///
+    ///     Writes the interval string into an IO-like object responding to `write`, in chunks,
+    ///     without building it in memory. The bitmap can't be modified while it is being written,
+    ///     except for a Roaring::ConcurrentBitmap, which writes a snapshot.
+    ///
+    ///     @param io [IO] The object to write to.
+    ///
+    ///     @return [Integer] The number of bytes written.
+    fn to_interval_string(rb_self: Obj<Self>, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(), (Option<Value>,), (), (), (), ()>(args)?;
 
-    bitmap_class.define_method("full?", method!(MutWrapper::is_full, 0))?;
+        let io = match args.optional.0 {
+            Some(io) if !io.is_nil() => io,
+            _ => {
+                let data = rb_self.get().0.read()?;
+                let mut buf = Vec::new();
+                interval::write(&mut buf, data._data.ranges())
+                    .map_err(|e| Error::new(errors::serialization_error(), e.to_string()))?;
+                return Ok(RString::from_slice(&buf).into_value());
+            }
+        };
+
+        // `write` runs arbitrary Ruby code, which must not be able to modify the bitmap while
+        // it is read-locked.
+        Self::read_for_callbacks(rb_self, |data| {
+            let mut writer = BufWriter::with_capacity(ruby_io::BUFFER_SIZE, RubyWriter::new(io));
+            let result = interval::write(&mut writer, data.ranges()).and_then(|_| writer.flush());
+            let (mut writer, _) = writer.into_parts();
+            result.map_err(|e| {
+                writer
+                    .take_raised()
+                    .unwrap_or_else(|| Error::new(errors::serialization_error(), e.to_string()))
+            })?;
+
+            Ok(writer.written().into_value())
+        })
+    }
+}
 
-    bitmap_class.define_method("max", method!(MutWrapper::max, 0))?;
+impl MutWrapper<RoaringBitmap> {
+    /// @yard
+    /// @def full
+    ///
+    /// Initializes a new bitmap with all bits set to 1.
+    ///
+    /// @example Initialize a full bitmap.
+    ///     rb = Roaring::Bitmap.full
+    ///     rb.full?    #=> true
+    ///
+    /// @return [Roaring::Bitmap] A new full bitmap.
+    fn new_full(class: RClass) -> Obj<Self> {
+        Self::wrap_as(RoaringBitmap::full(), class)
+    }
 
-    bitmap_class.define_method("min", method!(MutWrapper::min, 0))?;
+    /// @yard
+    /// @def mmap(path, verify: false)
+    ///
+    /// Maps a file holding a serialized bitmap into memory, read-only, and queries it in place,
+    /// like Roaring::FrozenBitmap.mmap. Returns a Roaring::FrozenBitmap rather than a
+    /// Roaring::Bitmap: it only answers the queries of Roaring::FrozenBitmap, and #to_bitmap
+    /// decodes it into a Roaring::Bitmap for everything else.
+    ///
+    /// Truncating the file while it is mapped is undefined behaviour, and typically kills the
+    /// process with SIGBUS.
+    ///
+    /// @param path [String, Pathname] The path of a file written from Roaring::Bitmap#serialize.
+    /// @param verify [Boolean] Checks the contents of every container too, reading the whole file.
+    ///
+    /// @example Mapping a bitmap file.
+    ///     File.binwrite("audience.bin", Roaring::Bitmap.from_a([1, 2, 3]).serialize)
+    ///     rb = Roaring::Bitmap.mmap("audience.bin")
+    ///     rb.contains(2)      #=> true
+    ///     rb.class            #=> Roaring::FrozenBitmap
+    ///
+    /// @return [Roaring::FrozenBitmap] A read-only bitmap over the file.
+    /// @raise [IOError] If the file can't be opened or mapped.
+    /// @raise [Roaring::DeserializationError] If the file is not a valid serialized bitmap.
+    fn mmap(args: &[Value]) -> Result<Obj<FrozenBitmap>, Error> {
+        FrozenBitmap::mmap(<FrozenBitmap as TypedData>::class(), args)
+    }
+}
 
-    bitmap_class.define_method("nth", method!(MutWrapper::select, 1))?;
+/// Defines the methods shared by Roaring::Bitmap and Roaring::Bitmap64 on `$class`, wrapping
+/// `$wrapper`. A macro rather than a generic function, as `method!` defines a function that
+/// can't use the generic parameters of the one it's in.
+macro_rules! define_bitmap_methods {
+    ($class:expr, $wrapper:ty) => {
+        $class.define_singleton_method("from_a", method!(<$wrapper>::from_array, 1))?;
 
-    bitmap_class.define_method("disjoint?", method!(MutWrapper::is_disjoint, 1))?;
+        $class.define_method("initialize", method!(<$wrapper>::initialize, -1))?;
+        $class.define_method("initialize_copy", method!(<$wrapper>::initialize_copy, 1))?;
 
-    bitmap_class.define_method("subset?", method!(MutWrapper::is_subset, 1))?;
+        $class.define_method("insert", method!(<$wrapper>::insert, 1))?;
 
-    bitmap_class.define_method("superset?", method!(MutWrapper::is_superset, 1))?;
+        $class.define_method("insert_many", method!(<$wrapper>::insert_many, 1))?;
 
-    bitmap_class.define_method("union", method!(MutWrapper::union, 1))?;
-    bitmap_class.define_method("union_len", method!(MutWrapper::union_len, 1))?;
+        $class.define_method("remove", method!(<$wrapper>::remove, 1))?;
 
-    bitmap_class.define_method("intersection", method!(MutWrapper::intersection, 1))?;
-    bitmap_class.define_method("intersection_len", method!(MutWrapper::intersection_len, 1))?;
+        $class.define_method("contains", method!(<$wrapper>::contains, 1))?;
 
-    bitmap_class.define_method("difference", method!(MutWrapper::difference, 1))?;
-    bitmap_class.define_method("difference_len", method!(MutWrapper::difference_len, 1))?;
+        $class.define_method("insert_range", method!(<$wrapper>::insert_range, 1))?;
+        $class.define_method("remove_range", method!(<$wrapper>::remove_range, 1))?;
+        $class.define_method("contains_range?", method!(<$wrapper>::contains_range, 1))?;
+        $class.define_method(
+            "range_cardinality",
+            method!(<$wrapper>::range_cardinality, 1),
+        )?;
+
+        $class.define_method("to_a", method!(<$wrapper>::to_vec, 0))?;
+
+        $class.define_method("clear", method!(<$wrapper>::clear, 0))?;
+
+        $class.define_method("cardinality", method!(<$wrapper>::len, 0))?;
+
+        $class.define_method("empty?", method!(<$wrapper>::is_empty, 0))?;
+
+        $class.define_method("full?", method!(<$wrapper>::is_full, 0))?;
+
+        $class.define_method("max", method!(<$wrapper>::max, 0))?;
+
+        $class.define_method("min", method!(<$wrapper>::min, 0))?;
+
+        $class.define_method("nth", method!(<$wrapper>::select, 1))?;
+
+        $class.define_method("disjoint?", method!(<$wrapper>::is_disjoint, 1))?;
+
+        $class.define_method("subset?", method!(<$wrapper>::is_subset, 1))?;
+
+        $class.define_method("superset?", method!(<$wrapper>::is_superset, 1))?;
+
+        $class.define_method("union", method!(<$wrapper>::union, 1))?;
+        $class.define_method("union_len", method!(<$wrapper>::union_len, 1))?;
+
+        $class.define_method("intersection", method!(<$wrapper>::intersection, 1))?;
+        $class.define_method("intersection_len", method!(<$wrapper>::intersection_len, 1))?;
+
+        $class.define_method("difference", method!(<$wrapper>::difference, 1))?;
+        $class.define_method("difference_len", method!(<$wrapper>::difference_len, 1))?;
+
+        $class.define_method(
+            "symmetric_difference",
+            method!(<$wrapper>::symmetric_difference, 1),
+        )?;
+        $class.define_method(
+            "symmetric_difference_len",
+            method!(<$wrapper>::symmetric_difference_len, 1),
+        )?;
+
+        $class.define_method("union!", method!(<$wrapper>::union_inplace, 1))?;
+        $class.define_method(
+            "intersection!",
+            method!(<$wrapper>::intersection_inplace, 1),
+        )?;
+        $class.define_method("difference!", method!(<$wrapper>::difference_inplace, 1))?;
+        $class.define_method(
+            "symmetric_difference!",
+            method!(<$wrapper>::symmetric_difference_inplace, 1),
+        )?;
+
+        $class.define_singleton_method("union_all", method!(<$wrapper>::union_all, 1))?;
+        $class.define_singleton_method("union_all_len", function!(<$wrapper>::union_all_len, 1))?;
+        $class.define_singleton_method(
+            "intersection_all",
+            method!(<$wrapper>::intersection_all, 1),
+        )?;
+        $class.define_singleton_method(
+            "intersection_all_len",
+            function!(<$wrapper>::intersection_all_len, 1),
+        )?;
+        $class.define_singleton_method("xor_all", method!(<$wrapper>::xor_all, 1))?;
+        $class.define_singleton_method("xor_all_len", function!(<$wrapper>::xor_all_len, 1))?;
+
+        $class.define_method("rank", method!(<$wrapper>::rank, 1))?;
+
+        $class.define_method("each", method!(<$wrapper>::each, -1))?;
+        $class.define_method("reverse_each", method!(<$wrapper>::reverse_each, 0))?;
+        $class.define_method("each_from", method!(<$wrapper>::each_from, 1))?;
+        $class.define_method("each_before", method!(<$wrapper>::each_before, 1))?;
+        $class.define_method("each_in", method!(<$wrapper>::each_in, 1))?;
+        $class.define_method("page", method!(<$wrapper>::page, -1))?;
+        $class.define_method("page_by_rank", method!(<$wrapper>::page_by_rank, 2))?;
+        $class.define_method("next_value", method!(<$wrapper>::next_value, 1))?;
+        $class.define_method("prev_value", method!(<$wrapper>::prev_value, 1))?;
+        $class.define_method("next_absent", method!(<$wrapper>::next_absent, 1))?;
+        $class.define_method("prev_absent", method!(<$wrapper>::prev_absent, 1))?;
+        $class.define_method("nearest", method!(<$wrapper>::nearest, 1))?;
+
+        $class.define_method("each_range", method!(<$wrapper>::each_range, -1))?;
+        $class.define_method("to_ranges", method!(<$wrapper>::to_ranges, -1))?;
+        $class.define_singleton_method("from_ranges", method!(<$wrapper>::from_ranges, 1))?;
+        $class.define_method("byte_size", method!(<$wrapper>::byte_size, 0))?;
+        $class.define_method("inspect", method!(<$wrapper>::inspect, 0))?;
+
+        $class.define_method("eql?", method!(<$wrapper>::eql, 1))?;
+
+        $class.define_method("serialize", method!(<$wrapper>::serialize, -1))?;
+        $class.define_singleton_method("deserialize", method!(<$wrapper>::deserialize, 1))?;
+        $class.define_method("serialize_to", method!(<$wrapper>::serialize_to, 1))?;
+        $class.define_singleton_method(
+            "deserialize_from",
+            method!(<$wrapper>::deserialize_from, 1),
+        )?;
+        $class.define_method("to_clickhouse", method!(<$wrapper>::to_clickhouse, 0))?;
+        $class.define_singleton_method("parse", method!(<$wrapper>::parse, 1))?;
+        $class.define_method(
+            "to_interval_string",
+            method!(<$wrapper>::to_interval_string, -1),
+        )?;
+        $class
+            .define_singleton_method("from_clickhouse", method!(<$wrapper>::from_clickhouse, 1))?;
+    };
+}
 
-    bitmap_class.define_method(
-        "symmetric_difference",
-        method!(MutWrapper::symmetric_difference, 1),
-    )?;
-    bitmap_class.define_method(
-        "symmetric_difference_len",
-        method!(MutWrapper::symmetric_difference_len, 1),
-    )?;
+#[magnus::init]
+fn init() -> Result<(), Error> {
+    // Methods only touch Ruby objects owned by the calling Ractor: IO objects given to
+    // serialize_to and friends are called back in that Ractor, and the classes cached by
+    // memoize! are shareable. Bitmaps are guarded by a lock, and frozen bitmaps and views
+    // (whose String is frozen and marked from the view) are the only ones shared.
+    #[cfg(ruby_gte_3_0)]
+    unsafe {
+        rb_sys::rb_ext_ractor_safe(true);
+    }
 
-    bitmap_class.define_method("rank", method!(MutWrapper::rank, 1))?;
+    let module = define_module("Roaring")?;
+    errors::init(module)?;
 
-    bitmap_class.define_method("each", method!(MutWrapper::each, 0))?;
+    let bitmap_class = module.define_class("Bitmap", Default::default())?;
+    define_alloc_func::<MutWrapper<RoaringBitmap>>(bitmap_class);
+    define_bitmap_methods!(bitmap_class, MutWrapper<RoaringBitmap>);
+    bitmap_class
+        .define_singleton_method("full", method!(MutWrapper::<RoaringBitmap>::new_full, 0))?;
+    bitmap_class
+        .define_singleton_method("mmap", function!(MutWrapper::<RoaringBitmap>::mmap, -1))?;
 
-    bitmap_class.define_method("byte_size", method!(MutWrapper::byte_size, 0))?;
+    module.define_class("ConcurrentBitmap", bitmap_class)?;
 
-    bitmap_class.define_method("eql?", method!(MutWrapper::eql, 1))?;
+    let bitmap64_class = module.define_class("Bitmap64", Default::default())?;
+    define_alloc_func::<MutWrapper<RoaringTreemap>>(bitmap64_class);
+    define_bitmap_methods!(bitmap64_class, MutWrapper<RoaringTreemap>);
 
-    bitmap_class.define_method("serialize", method!(MutWrapper::serialize, 0))?;
-    bitmap_class.define_singleton_method("deserialize", function!(MutWrapper::deserialize, 1))?;
+    frozen_bitmap::init(module)?;
 
     Ok(())
 }
@@ -0,0 +1,156 @@
+use std::{
+    ptr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+    },
+};
+
+use magnus::Error;
+
+use crate::{errors, gvl};
+
+/// The inner state of a bitmap object, shared between Ruby threads.
+///
+/// Locks are only ever held for the duration of a single method call. When a lock is
+/// contended, the waiting thread releases the GVL so the holder (typically running a large
+/// operation without the GVL) can finish, and polls the lock until it's free. Thread#raise and
+/// Thread#kill interrupt the wait, which then raises instead of locking.
+pub struct Lock<T> {
+    data: RwLock<T>,
+    iterators: AtomicUsize,
+}
+
+/// Marks a bitmap as being iterated until dropped.
+pub struct IterationGuard<'a>(&'a AtomicUsize);
+
+impl Drop for IterationGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<T> Lock<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            data: RwLock::new(data),
+            iterators: AtomicUsize::new(0),
+        }
+    }
+
+    /// Locks for reading, waiting without the GVL if a writer holds the lock.
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, T>, Error> {
+        match self.try_read() {
+            Some(guard) => Ok(guard),
+            None => gvl::wait_without_gvl(|| self.try_read()),
+        }
+    }
+
+    /// Locks for reading without blocking, or returns None if the lock is held by a writer.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        match self.data.try_read() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// Locks for writing, waiting without the GVL if the lock is held.
+    ///
+    /// Raises instead of waiting while the value is being iterated, as the iterating thread
+    /// holds a read lock while it yields to Ruby and may itself be the caller.
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, T>, Error> {
+        if self.iterators.load(Ordering::Acquire) > 0 {
+            return Err(Error::new(
+                errors::concurrent_modification_error(),
+                "can't modify bitmap during iteration",
+            ));
+        }
+
+        match self.try_write() {
+            Some(guard) => Ok(guard),
+            None => gvl::wait_without_gvl(|| self.try_write()),
+        }
+    }
+
+    /// Locks for writing without blocking, or returns None if the lock is held.
+    fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        match self.data.try_write() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// Marks the value as being iterated, making writers raise until the guard is dropped.
+    pub fn iterate(&self) -> IterationGuard<'_> {
+        self.iterators.fetch_add(1, Ordering::AcqRel);
+        IterationGuard(&self.iterators)
+    }
+
+    /// Read-locks two values and passes them to `func`.
+    ///
+    /// The locks are taken in address order so that two threads combining the same pair of
+    /// values in opposite orders can't deadlock, and only once if both are the same value.
+    pub fn read_both<R>(&self, other: &Self, func: impl FnOnce(&T, &T) -> R) -> Result<R, Error> {
+        if ptr::eq(self, other) {
+            let guard = self.read()?;
+            return Ok(func(&guard, &guard));
+        }
+
+        if (self as *const Self) < (other as *const Self) {
+            let lhs = self.read()?;
+            let rhs = other.read()?;
+            Ok(func(&lhs, &rhs))
+        } else {
+            let rhs = other.read()?;
+            let lhs = self.read()?;
+            Ok(func(&lhs, &rhs))
+        }
+    }
+
+    /// Write-locks `self` and read-locks `other`, in address order, and passes them to `func`.
+    /// `self` and `other` must not be the same value.
+    pub fn write_read<R>(
+        &self,
+        other: &Self,
+        func: impl FnOnce(&mut T, &T) -> R,
+    ) -> Result<R, Error> {
+        debug_assert!(!ptr::eq(self, other));
+
+        if (self as *const Self) < (other as *const Self) {
+            let mut lhs = self.write()?;
+            let rhs = other.read()?;
+            Ok(func(&mut lhs, &rhs))
+        } else {
+            let rhs = other.read()?;
+            let mut lhs = self.write()?;
+            Ok(func(&mut lhs, &rhs))
+        }
+    }
+
+    /// Read-locks every value, in address order and once per distinct value, and passes them
+    /// to `func` in their original order.
+    pub fn read_all<R>(locks: &[&Self], func: impl FnOnce(Vec<&T>) -> R) -> Result<R, Error> {
+        let mut order: Vec<&Self> = locks.to_vec();
+        order.sort_by_key(|lock| *lock as *const Self);
+        order.dedup_by_key(|lock| *lock as *const Self);
+
+        let guards: Vec<(*const Self, RwLockReadGuard<'_, T>)> = order
+            .iter()
+            .map(|lock| Ok((*lock as *const Self, lock.read()?)))
+            .collect::<Result<_, Error>>()?;
+
+        let values = locks
+            .iter()
+            .map(|lock| {
+                let i = guards
+                    .binary_search_by_key(&(*lock as *const Self), |(ptr, _)| *ptr)
+                    .unwrap();
+                &*guards[i].1
+            })
+            .collect();
+
+        Ok(func(values))
+    }
+}
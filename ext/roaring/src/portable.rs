@@ -0,0 +1,62 @@
+//! Reads bitmaps in the portable serialization formats shared with CRoaring, Java and Go,
+//! run containers included.
+//!
+//! The 64-bit format is a little-endian u64 count of buckets, followed for each bucket by the
+//! little-endian u32 high 32 bits of its values and a 32-bit bitmap of their low 32 bits, in
+//! strictly ascending order of high bits. It's what CRoaring's
+//! `roaring64_bitmap_portable_serialize`, Java's `Roaring64NavigableMap#serializePortable` and
+//! Go's `roaring64.Bitmap#WriteTo` write, and also what a RoaringTreemap serializes to.
+//!
+//! The buckets are decoded by RoaringBitmap::deserialize_from, container by container.
+//! RoaringTreemap::deserialize_from would let a bucket replace an earlier one with the same high
+//! bits, so the buckets are read here to check their order.
+
+use std::io::{self, Read};
+
+use roaring::{RoaringBitmap, RoaringTreemap};
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Decodes a 32-bit bitmap from the start of `bytes`, and returns it with the number of bytes
+/// it took.
+pub fn decode32(bytes: &[u8]) -> io::Result<(RoaringBitmap, usize)> {
+    let mut rest = bytes;
+    let bitmap = RoaringBitmap::deserialize_from(&mut rest)?;
+    Ok((bitmap, bytes.len() - rest.len()))
+}
+
+/// Decodes a 64-bit bitmap from the start of `bytes`, and returns it with the number of bytes
+/// it took.
+pub fn decode64(bytes: &[u8]) -> io::Result<(RoaringTreemap, usize)> {
+    let mut rest = bytes;
+    let treemap = read64(&mut rest)?;
+    Ok((treemap, bytes.len() - rest.len()))
+}
+
+/// Reads a 64-bit bitmap from `reader`, one bucket at a time, consuming only its bytes. Empty
+/// buckets are dropped.
+pub fn read64(mut reader: impl Read) -> io::Result<RoaringTreemap> {
+    let mut count = [0; 8];
+    reader.read_exact(&mut count)?;
+    let count = u64::from_le_bytes(count);
+
+    let mut buckets = Vec::new();
+    let mut last_key = None;
+    for _ in 0..count {
+        let mut key = [0; 4];
+        reader.read_exact(&mut key)?;
+        let key = u32::from_le_bytes(key);
+        if last_key.is_some_and(|last| last >= key) {
+            return Err(invalid("bucket keys are not sorted"));
+        }
+        last_key = Some(key);
+
+        let bitmap = RoaringBitmap::deserialize_from(&mut reader)?;
+        if !bitmap.is_empty() {
+            buckets.push((key, bitmap));
+        }
+    }
+    Ok(RoaringTreemap::from_bitmaps(buckets))
+}
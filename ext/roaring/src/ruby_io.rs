@@ -0,0 +1,109 @@
+//! `Read` and `Write` adapters over Ruby IO-like objects.
+
+use std::io::{self, Read, Write};
+
+use magnus::{prelude::*, Error, RString, Value};
+
+/// How many bytes are buffered before calling the Ruby object's `write`.
+pub const BUFFER_SIZE: usize = 1 << 16;
+
+/// Writes to any Ruby object responding to `write`, such as a File, Socket, StringIO or
+/// Zlib::GzipWriter. Every call to `write` calls into Ruby, so it is meant to be buffered.
+pub struct RubyWriter {
+    io: Value,
+    written: u64,
+    raised: Option<Error>,
+}
+
+impl RubyWriter {
+    pub fn new(io: Value) -> Self {
+        Self {
+            io,
+            written: 0,
+            raised: None,
+        }
+    }
+
+    /// The number of bytes the Ruby object accepted.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Takes the exception raised by `write`, which the I/O error returned to Rust stands for.
+    pub fn take_raised(&mut self) -> Option<Error> {
+        self.raised.take()
+    }
+}
+
+impl Write for RubyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self
+            .io
+            .funcall::<_, _, usize>("write", (RString::from_slice(buf),))
+        {
+            Ok(n) => {
+                let n = n.min(buf.len());
+                self.written += n as u64;
+                Ok(n)
+            }
+            Err(e) => {
+                self.raised = Some(e);
+                Err(io::Error::other("write raised"))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads from any Ruby object responding to `read(length)`, such as a File, Socket, StringIO
+/// or Zlib::GzipReader. Only the requested bytes are read, so the object is left positioned
+/// right after the data that was consumed.
+pub struct RubyReader {
+    io: Value,
+    raised: Option<Error>,
+}
+
+impl RubyReader {
+    pub fn new(io: Value) -> Self {
+        Self { io, raised: None }
+    }
+
+    /// Takes the exception raised by `read`, which the I/O error returned to Rust stands for.
+    pub fn take_raised(&mut self) -> Option<Error> {
+        self.raised.take()
+    }
+}
+
+impl Read for RubyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self
+            .io
+            .funcall::<_, _, Option<RString>>("read", (buf.len(),))
+        {
+            // nil means end of file.
+            Ok(None) => Ok(0),
+            Ok(Some(chunk)) => {
+                let bytes = unsafe { chunk.as_slice() };
+                if bytes.len() > buf.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "read returned more bytes than requested",
+                    ));
+                }
+                buf[..bytes.len()].copy_from_slice(bytes);
+                Ok(bytes.len())
+            }
+            Err(e) => {
+                self.raised = Some(e);
+                Err(io::Error::other("read raised"))
+            }
+        }
+    }
+}
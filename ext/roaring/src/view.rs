@@ -0,0 +1,422 @@
+//! Queries a bitmap in the portable serialization format straight from its bytes.
+//!
+//! Parsing only reads the container headers. Containers are decoded on demand by each query,
+//! so the bytes must stay alive and unchanged for as long as the view is used.
+//!
+//! Parsing checks the layout: that the headers are well formed and every container lies within
+//! the bytes, so no query reads out of them. The contents of the containers are only checked by
+//! View::verify, which reads them all; queries over a corrupt container give wrong answers.
+
+use std::{fmt, iter::FusedIterator};
+
+const SERIAL_COOKIE_NO_RUNCONTAINER: u32 = 12346;
+const SERIAL_COOKIE: u32 = 12347;
+const NO_OFFSET_THRESHOLD: usize = 4;
+const ARRAY_LIMIT: u32 = 4096;
+const BITMAP_BYTES: usize = 8192;
+
+/// Why a byte string isn't a valid serialized bitmap.
+#[derive(Debug)]
+pub struct InvalidData(&'static str);
+
+impl fmt::Display for InvalidData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Array,
+    Bitmap,
+    Run,
+}
+
+struct Container {
+    key: u16,
+    len: u32,
+    kind: Kind,
+    /// Start of the container's data within the serialized bytes.
+    offset: usize,
+}
+
+/// The container directory of a serialized bitmap.
+pub struct View {
+    containers: Vec<Container>,
+    len: u64,
+    /// Number of bytes of the serialized bitmap.
+    size: usize,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, InvalidData> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(InvalidData("unexpected end of data"))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
+
+impl View {
+    /// Reads the container directory of `bytes`, checking that every container lies within them
+    /// but not what the containers hold.
+    pub fn parse(bytes: &[u8]) -> Result<Self, InvalidData> {
+        let cookie = read_u32(bytes, 0)?;
+        let (count, run_bitset, mut pos) = if cookie & 0xFFFF == SERIAL_COOKIE {
+            let count = (cookie >> 16) as usize + 1;
+            let bitset_len = count.div_ceil(8);
+            let bitset = bytes
+                .get(4..4 + bitset_len)
+                .ok_or(InvalidData("unexpected end of data"))?;
+            (count, Some(bitset), 4 + bitset_len)
+        } else if cookie == SERIAL_COOKIE_NO_RUNCONTAINER {
+            (read_u32(bytes, 4)? as usize, None, 8)
+        } else {
+            return Err(InvalidData("unknown cookie value"));
+        };
+
+        if count > 1 << 16 {
+            return Err(InvalidData("too many containers"));
+        }
+
+        let header = bytes
+            .get(pos..pos + count * 4)
+            .ok_or(InvalidData("unexpected end of data"))?;
+        pos += count * 4;
+        if run_bitset.is_none() || count >= NO_OFFSET_THRESHOLD {
+            // The offsets are implied by the container sizes, so they are skipped.
+            pos += count * 4;
+        }
+
+        let mut containers = Vec::with_capacity(count);
+        let mut len = 0;
+        for i in 0..count {
+            let key = read_u16(header, i * 4);
+            let card = read_u16(header, i * 4 + 2) as u32 + 1;
+            if containers
+                .last()
+                .is_some_and(|prev: &Container| prev.key >= key)
+            {
+                return Err(InvalidData("container keys are not sorted"));
+            }
+
+            let is_run = run_bitset.is_some_and(|bitset| bitset[i / 8] & (1 << (i % 8)) != 0);
+            let (kind, size) = if is_run {
+                if bytes.len() < pos + 2 {
+                    return Err(InvalidData("unexpected end of data"));
+                }
+                let runs = read_u16(bytes, pos) as usize;
+                if runs == 0 {
+                    return Err(InvalidData("empty run container"));
+                }
+                (Kind::Run, 2 + runs * 4)
+            } else if card <= ARRAY_LIMIT {
+                (Kind::Array, card as usize * 2)
+            } else {
+                (Kind::Bitmap, BITMAP_BYTES)
+            };
+
+            if bytes.len() < pos + size {
+                return Err(InvalidData("unexpected end of data"));
+            }
+            let container = Container {
+                key,
+                len: card,
+                kind,
+                offset: pos,
+            };
+            containers.push(container);
+            len += card as u64;
+            pos += size;
+        }
+
+        Ok(Self {
+            containers,
+            len,
+            size: pos,
+        })
+    }
+
+    /// Checks the contents of every container of `bytes`, reading all of them.
+    pub fn verify(&self, bytes: &[u8]) -> Result<(), InvalidData> {
+        self.containers.iter().try_for_each(|c| validate(bytes, c))
+    }
+
+    /// Returns the number of integers in the bitmap.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the number of bytes the serialized bitmap takes.
+    pub fn serialized_size(&self) -> usize {
+        self.size
+    }
+
+    fn find(&self, key: u16) -> Result<usize, usize> {
+        self.containers.binary_search_by_key(&key, |c| c.key)
+    }
+
+    /// Returns true if the bitmap contains `value`.
+    pub fn contains(&self, bytes: &[u8], value: u32) -> bool {
+        let (key, low) = ((value >> 16) as u16, value as u16);
+        match self.find(key) {
+            Ok(i) => container_rank(bytes, &self.containers[i], low).1,
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the number of integers that are <= `value`.
+    pub fn rank(&self, bytes: &[u8], value: u32) -> u64 {
+        let (key, low) = ((value >> 16) as u16, value as u16);
+        let (before, container) = match self.find(key) {
+            Ok(i) => (i, Some(&self.containers[i])),
+            Err(i) => (i, None),
+        };
+        let full: u64 = self.containers[..before].iter().map(|c| c.len as u64).sum();
+        full + container.map_or(0, |c| container_rank(bytes, c, low).0 as u64)
+    }
+
+    /// Returns the smallest integer in the bitmap.
+    pub fn min(&self, bytes: &[u8]) -> Option<u32> {
+        self.iter(bytes).next()
+    }
+
+    /// Returns the largest integer in the bitmap.
+    pub fn max(&self, bytes: &[u8]) -> Option<u32> {
+        let c = self.containers.last()?;
+        let high = (c.key as u32) << 16;
+        let low = match c.kind {
+            Kind::Array => read_u16(bytes, c.offset + (c.len as usize - 1) * 2),
+            Kind::Bitmap => (0..1024)
+                .rev()
+                .map(|w| (w, read_u64(bytes, c.offset + w * 8)))
+                .find(|(_, word)| *word != 0)
+                .map(|(w, word)| (w * 64 + 63 - word.leading_zeros() as usize) as u16)?,
+            Kind::Run => {
+                let runs = read_u16(bytes, c.offset) as usize;
+                let run = c.offset + 2 + (runs - 1) * 4;
+                read_u16(bytes, run).wrapping_add(read_u16(bytes, run + 2))
+            }
+        };
+        Some(high | low as u32)
+    }
+
+    /// Iterates over the integers of the bitmap in ascending order.
+    pub fn iter<'a>(&'a self, bytes: &'a [u8]) -> Iter<'a> {
+        Iter {
+            bytes,
+            containers: self.containers.iter(),
+            current: None,
+        }
+    }
+}
+
+/// Checks that the values of a container are sorted, that its runs end within 16 bits, and
+/// that it holds as many values as its header says, which queries rely on.
+fn validate(bytes: &[u8], c: &Container) -> Result<(), InvalidData> {
+    let card = match c.kind {
+        Kind::Array => {
+            let values = (0..c.len as usize).map(|i| read_u16(bytes, c.offset + i * 2));
+            if values.clone().zip(values.skip(1)).any(|(a, b)| a >= b) {
+                return Err(InvalidData("array container values are not sorted"));
+            }
+            c.len
+        }
+        Kind::Bitmap => (0..1024)
+            .map(|w| read_u64(bytes, c.offset + w * 8).count_ones())
+            .sum(),
+        Kind::Run => {
+            let runs = read_u16(bytes, c.offset) as usize;
+            let mut card = 0;
+            let mut prev_end = None;
+            for r in 0..runs {
+                let start = read_u16(bytes, c.offset + 2 + r * 4) as u32;
+                let end = start + read_u16(bytes, c.offset + 4 + r * 4) as u32;
+                if end > u16::MAX as u32 {
+                    return Err(InvalidData("run container run exceeds 16 bits"));
+                }
+                if prev_end.is_some_and(|prev| start <= prev) {
+                    return Err(InvalidData("run container runs are not sorted"));
+                }
+                card += end - start + 1;
+                prev_end = Some(end);
+            }
+            card
+        }
+    };
+
+    if card != c.len {
+        return Err(InvalidData("container cardinality doesn't match"));
+    }
+    Ok(())
+}
+
+/// Returns the number of values of the container that are <= `low`, and whether `low` is
+/// one of them.
+fn container_rank(bytes: &[u8], c: &Container, low: u16) -> (u32, bool) {
+    match c.kind {
+        Kind::Array => {
+            let (mut lo, mut hi) = (0, c.len as usize);
+            while lo < hi {
+                let mid = (lo + hi) / 2;
+                if read_u16(bytes, c.offset + mid * 2) <= low {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            let found = lo > 0 && read_u16(bytes, c.offset + (lo - 1) * 2) == low;
+            (lo as u32, found)
+        }
+        Kind::Bitmap => {
+            let (word, bit) = (low as usize / 64, low as u32 % 64);
+            let full: u32 = (0..word)
+                .map(|w| read_u64(bytes, c.offset + w * 8).count_ones())
+                .sum();
+            let last = read_u64(bytes, c.offset + word * 8);
+            let mask = u64::MAX >> (63 - bit);
+            (full + (last & mask).count_ones(), last & (1 << bit) != 0)
+        }
+        Kind::Run => {
+            let runs = read_u16(bytes, c.offset) as usize;
+            let mut rank = 0;
+            for r in 0..runs {
+                let start = read_u16(bytes, c.offset + 2 + r * 4) as u32;
+                let end = start + read_u16(bytes, c.offset + 4 + r * 4) as u32;
+                if start > low as u32 {
+                    return (rank, false);
+                }
+                if end >= low as u32 {
+                    return (rank + low as u32 - start + 1, true);
+                }
+                rank += end - start + 1;
+            }
+            (rank, false)
+        }
+    }
+}
+
+enum ContainerIter {
+    Array {
+        offset: usize,
+        index: usize,
+        len: usize,
+    },
+    Bitmap {
+        offset: usize,
+        index: usize,
+        word: u64,
+    },
+    Run {
+        offset: usize,
+        runs: usize,
+        run: usize,
+        next: u32,
+        end: u32,
+    },
+}
+
+impl ContainerIter {
+    fn new(bytes: &[u8], c: &Container) -> Self {
+        match c.kind {
+            Kind::Array => ContainerIter::Array {
+                offset: c.offset,
+                index: 0,
+                len: c.len as usize,
+            },
+            Kind::Bitmap => ContainerIter::Bitmap {
+                offset: c.offset,
+                index: 0,
+                word: read_u64(bytes, c.offset),
+            },
+            Kind::Run => ContainerIter::Run {
+                offset: c.offset + 2,
+                runs: read_u16(bytes, c.offset) as usize,
+                run: 0,
+                next: 1,
+                end: 0,
+            },
+        }
+    }
+
+    fn next(&mut self, bytes: &[u8]) -> Option<u16> {
+        match self {
+            ContainerIter::Array { offset, index, len } => {
+                if index >= len {
+                    return None;
+                }
+                let value = read_u16(bytes, *offset + *index * 2);
+                *index += 1;
+                Some(value)
+            }
+            ContainerIter::Bitmap {
+                offset,
+                index,
+                word,
+            } => {
+                while *word == 0 {
+                    *index += 1;
+                    if *index >= 1024 {
+                        return None;
+                    }
+                    *word = read_u64(bytes, *offset + *index * 8);
+                }
+                let bit = word.trailing_zeros() as usize;
+                *word &= *word - 1;
+                Some((*index * 64 + bit) as u16)
+            }
+            ContainerIter::Run {
+                offset,
+                runs,
+                run,
+                next,
+                end,
+            } => {
+                if *next > *end {
+                    if *run >= *runs {
+                        return None;
+                    }
+                    *next = read_u16(bytes, *offset + *run * 4) as u32;
+                    *end = *next + read_u16(bytes, *offset + *run * 4 + 2) as u32;
+                    *run += 1;
+                }
+                let value = *next as u16;
+                *next += 1;
+                Some(value)
+            }
+        }
+    }
+}
+
+/// An iterator over the integers of a View, in ascending order.
+pub struct Iter<'a> {
+    bytes: &'a [u8],
+    containers: std::slice::Iter<'a, Container>,
+    current: Option<(u32, ContainerIter)>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        loop {
+            if let Some((high, container)) = &mut self.current {
+                if let Some(low) = container.next(self.bytes) {
+                    return Some(*high | low as u32);
+                }
+            }
+            let c = self.containers.next()?;
+            self.current = Some(((c.key as u32) << 16, ContainerIter::new(self.bytes, c)));
+        }
+    }
+}
+
+impl FusedIterator for Iter<'_> {}
@@ -11,56 +11,125 @@ rescue LoadError
 end
 
 module Roaring
-  class Error < StandardError; end
+  # Roaring::Error and its subclasses (DeserializationError and its ChecksumError,
+  # SerializationError, ParseError, InvalidValueError) are defined by the native extension.
 
-  class Bitmap
+  class ParseError
+    # @return [Integer] The byte offset of the problem within the parsed string.
+    attr_reader :offset
+  end
+
+  [Bitmap, Bitmap64].each do |klass|
+    klass.class_eval do
+      include Enumerable
+
+      alias_method :size, :cardinality
+      alias_method :count, :cardinality
+      alias_method :length, :cardinality
+
+      alias_method :&, :intersection
+      alias_method :and, :intersection
+      alias_method :and_len, :intersection_len
+
+      alias_method :|, :union
+      alias_method :or, :union
+      alias_method :or_len, :union_len
+
+      alias_method :-, :difference
+      alias_method :and_not, :difference
+      alias_method :and_not_len, :difference_len
+
+      alias_method :^, :symmetric_difference
+      alias_method :xor, :symmetric_difference
+      alias_method :xor_len, :symmetric_difference_len
+
+      alias_method :or!, :union!
+      alias_method :and!, :intersection!
+      alias_method :and_not!, :difference!
+      alias_method :xor!, :symmetric_difference!
+
+      alias_method :<<, :insert
+
+      alias_method :delete, :remove
+
+      alias_method :include?, :contains
+      alias_method :member?, :contains
+      alias_method :contains?, :contains
+
+      alias_method :reset, :clear
+
+      alias_method :first, :min
+      alias_method :last, :max
+
+      alias_method :==, :eql?
+
+      def hash
+        to_a.hash
+      end
+
+      # Prints the same description as #inspect with +pp+.
+      def pretty_print(pp)
+        pp.text(inspect)
+      end
+
+      def self._load(args)
+        deserialize(args)
+      end
+
+      def _dump(_level)
+        serialize(checksum: true)
+      end
+    end
+  end
+
+  # A bitmap of integers in 0...2**64, backed by a map from the upper 32 bits of its integers
+  # to a bitmap of their lower 32 bits. It has the methods of Roaring::Bitmap, except ::full
+  # and ::mmap, and reads and writes the 64-bit portable format of CRoaring, Java and Go.
+  #
+  # @example Storing 64-bit ids.
+  #     rb = Roaring::Bitmap64.from_a([1, 2**40])
+  #     rb.insert_range((2**63)..(2**63 + 2))
+  #     rb.cardinality     #=> 5
+  class Bitmap64
+  end
+
+  # A Roaring::Bitmap meant to be shared between threads that modify it.
+  #
+  # Every bitmap is guarded by a reader/writer lock: readers such as #contains run
+  # concurrently, writers are serialized, and a thread waiting for the lock releases
+  # the GVL instead of blocking other threads. Where a plain Bitmap raises if it is
+  # modified while being iterated, ConcurrentBitmap#each iterates over a snapshot taken
+  # when iteration starts, so writers (including the block itself) never conflict with
+  # readers. Taking the snapshot copies the bitmap.
+  #
+  # @example Iterating while another thread writes.
+  #     rb = Roaring::ConcurrentBitmap.new
+  #     rb.insert_many([1, 2, 3])
+  #     writer = Thread.new { 100.times { |i| rb.insert(i) } }
+  #     rb.each { |i| rb.remove(i) if i.odd? }
+  #     writer.join
+  class ConcurrentBitmap < Bitmap
+  end
+
+  class FrozenBitmap
     include Enumerable
 
+    private_class_method :new
+
     alias_method :size, :cardinality
     alias_method :count, :cardinality
     alias_method :length, :cardinality
 
     alias_method :&, :intersection
-    alias_method :and, :intersection
-    alias_method :and_len, :intersection_len
-
     alias_method :|, :union
-    alias_method :or, :union
-    alias_method :or_len, :union_len
-
     alias_method :-, :difference
-    alias_method :and_not, :difference
-    alias_method :and_not_len, :difference_len
-
     alias_method :^, :symmetric_difference
-    alias_method :xor, :symmetric_difference
-    alias_method :xor_len, :symmetric_difference_len
-
-    alias_method :<<, :insert
-
-    alias_method :delete, :remove
 
     alias_method :include?, :contains
     alias_method :member?, :contains
     alias_method :contains?, :contains
 
-    alias_method :reset, :clear
-
     alias_method :first, :min
     alias_method :last, :max
-
-    alias_method :==, :eql?
-
-    def hash
-      to_a.hash
-    end
-
-    def self._load(args)
-      deserialize(args)
-    end
-
-    def _dump(_level)
-      serialize
-    end
   end
 end
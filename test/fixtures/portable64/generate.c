@@ -0,0 +1,117 @@
+// Writes the 64-bit portable format fixtures of this directory with CRoaring.
+//
+// Build it against the CRoaring amalgamation (roaring.c and roaring.h, as shipped with each
+// CRoaring release) and run it from this directory:
+//
+//     cc -O2 -I "$CROARING" generate.c "$CROARING/roaring.c" -o /tmp/generate && /tmp/generate
+//
+// The fixtures were last generated with CRoaring 4.7.1.
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "roaring.h"
+
+static char *serialize(const roaring64_bitmap_t *r, size_t *len) {
+    *len = roaring64_bitmap_portable_size_in_bytes(r);
+    char *buf = malloc(*len);
+    if (buf == NULL || roaring64_bitmap_portable_serialize(r, buf) != *len) {
+        fprintf(stderr, "serialization failed\n");
+        exit(1);
+    }
+    return buf;
+}
+
+static void write_bytes(const char *name, const char *buf, size_t len) {
+    FILE *file = fopen(name, "wb");
+    if (file == NULL || fwrite(buf, 1, len, file) != len || fclose(file) != 0) {
+        perror(name);
+        exit(1);
+    }
+}
+
+// Writes `r` to `name` and frees it.
+static void write_fixture(const char *name, roaring64_bitmap_t *r) {
+    size_t len;
+    char *buf = serialize(r, &len);
+    write_bytes(name, buf, len);
+    free(buf);
+    roaring64_bitmap_free(r);
+}
+
+int main(void) {
+    write_fixture("empty.bin", roaring64_bitmap_create());
+
+    roaring64_bitmap_t *r = roaring64_bitmap_create();
+    for (uint64_t v = 0; v < 10; v++) {
+        roaring64_bitmap_add(r, v);
+    }
+    write_fixture("32bit_values.bin", r);
+
+    r = roaring64_bitmap_create();
+    for (uint64_t i = 0; i < 10; i++) {
+        for (uint64_t j = 0; j < 10; j++) {
+            roaring64_bitmap_add(r, (i << 32) + j * 3);
+        }
+    }
+    write_fixture("spread_values.bin", r);
+
+    r = roaring64_bitmap_create();
+    roaring64_bitmap_add_range_closed(r, UINT64_MAX - 0xFFFF, UINT64_MAX);
+    roaring64_bitmap_run_optimize(r);
+    write_fixture("high_values.bin", r);
+
+    // Keep in sync with test_portable_run_containers.
+    const uint64_t bucket = 1ULL << 32;
+    r = roaring64_bitmap_create();
+    for (uint64_t v = 0; v < 40000; v += 7) {
+        roaring64_bitmap_add(r, bucket + v);
+    }
+    for (uint64_t v = 0; v < 65536; v += 2) {
+        roaring64_bitmap_add(r, bucket + 65536 + v);
+    }
+    roaring64_bitmap_add_range_closed(r, bucket + 2 * 65536 + 100, bucket + 2 * 65536 + 59999);
+    const uint64_t few[] = {5, 6, 7, 1000, 1001};
+    for (size_t i = 0; i < sizeof(few) / sizeof(few[0]); i++) {
+        roaring64_bitmap_add(r, bucket + 3 * 65536 + few[i]);
+    }
+    roaring64_bitmap_add_range_closed(r, 1ULL << 40, (1ULL << 40) + 999);
+    roaring64_bitmap_add(r, (1ULL << 63) + 1);
+    roaring64_bitmap_add(r, UINT64_MAX);
+    roaring64_bitmap_run_optimize(r);
+    write_fixture("mixed_containers.bin", r);
+
+    // CRoaring never writes buckets out of order, so two single-bucket bitmaps are spliced
+    // into one whose buckets have the high bits 2 then 1.
+    roaring64_bitmap_t *second = roaring64_bitmap_create();
+    roaring64_bitmap_add(second, (2ULL << 32) + 1);
+    roaring64_bitmap_t *first = roaring64_bitmap_create();
+    roaring64_bitmap_add(first, (1ULL << 32) + 1);
+    size_t second_len, first_len;
+    char *second_buf = serialize(second, &second_len);
+    char *first_buf = serialize(first, &first_len);
+    size_t len = second_len + first_len - 8;
+    char *buf = malloc(len);
+    uint64_t count = 2;
+    memcpy(buf, &count, 8);  // little-endian hosts only, like the format
+    memcpy(buf + 8, second_buf + 8, second_len - 8);
+    memcpy(buf + second_len, first_buf + 8, first_len - 8);
+    write_bytes("unsorted_keys.bin", buf, len);
+    free(buf);
+    free(first_buf);
+    free(second_buf);
+    roaring64_bitmap_free(first);
+    roaring64_bitmap_free(second);
+
+    r = roaring64_bitmap_create();
+    roaring64_bitmap_add_range_closed(r, 1ULL << 32, (1ULL << 32) + 99);
+    roaring64_bitmap_add(r, 1ULL << 40);
+    buf = serialize(r, &len);
+    write_bytes("truncated.bin", buf, len - 3);
+    free(buf);
+    roaring64_bitmap_free(r);
+
+    return 0;
+}
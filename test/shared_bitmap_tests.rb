@@ -0,0 +1,270 @@
+# frozen_string_literal: true
+
+require "pp"
+require "set"
+require "stringio"
+
+# Tests shared by Roaring::Bitmap and Roaring::Bitmap64. Including classes define
+# `bitmap_class`, `max_value`, the largest integer it holds, and `values`, a few sorted,
+# non-consecutive integers spread over 0..max_value, ending with max_value.
+module SharedBitmapTests
+  def test_insert
+    bitmap = bitmap_class.new
+    assert bitmap.insert(values[-2]) == true
+    assert bitmap.insert(values[-2]) == false
+    assert bitmap.insert(max_value) == true
+  end
+
+  def test_insert_out_of_range
+    bitmap = bitmap_class.new
+    assert_raises(RangeError) { bitmap.insert(max_value + 1) }
+    assert_raises(RangeError) { bitmap.insert(-1) }
+  end
+
+  def test_remove
+    bitmap = bitmap_class.from_a(values)
+    assert bitmap.remove(values[-2]) == true
+    assert bitmap.remove(values[-2]) == false
+  end
+
+  def test_contains
+    bitmap = bitmap_class.from_a(values)
+    assert bitmap.contains(values[-2]) == true
+    assert bitmap.contains(values[-2] + 1) == false
+  end
+
+  def test_rank_and_nth
+    bitmap = bitmap_class.from_a(values)
+    assert bitmap.rank(values[1]) == 2
+    assert bitmap.rank(max_value) == values.size
+    assert bitmap.nth(2) == values[2]
+    assert_nil bitmap.nth(values.size)
+  end
+
+  def test_each
+    bitmap = bitmap_class.from_a(values)
+    ary = []
+    bitmap.each { |i| ary << i }
+    assert ary == values
+    assert bitmap.each.map { |i| i - 1 } == values.map { |i| i - 1 }
+    assert bitmap.each.size == values.size
+  end
+
+  def test_each_snapshot
+    bitmap = bitmap_class.from_a(values)
+    assert_raises(Roaring::ConcurrentModificationError) do
+      bitmap.each { |i| bitmap.remove(i) }
+    end
+
+    seen = []
+    bitmap.each(snapshot: true) do |i|
+      seen << i
+      bitmap.remove(i) if i.odd?
+    end
+    assert seen == values
+    assert bitmap.to_a == values.select(&:even?)
+
+    enum = bitmap.each(snapshot: true)
+    bitmap.insert(3)
+    assert enum.to_a == values.select(&:even?)
+  end
+
+  def test_page
+    bitmap = bitmap_class.from_a(values)
+    first, after_first = bitmap.page(limit: 2)
+    assert first == values[0, 2]
+    assert after_first.match?(/\A[A-Za-z0-9_-]+\z/)
+    assert bitmap.page(after: nil, limit: 2) == [values[0, 2], after_first]
+    assert bitmap.page(after: after_first, limit: values.size) == [values[2..], nil]
+
+    # The cursor of a page still points after it once its last integer is removed.
+    bitmap.remove(values[1])
+    assert bitmap.page(after: after_first, limit: values.size) == [values[2..], nil]
+    bitmap.insert(values[1])
+
+    ids = []
+    cursor = nil
+    loop do
+      page, cursor = bitmap.page(after: cursor, limit: 2)
+      ids.concat(page)
+      break unless cursor
+    end
+    assert ids == values
+
+    assert bitmap.page_by_rank(1, 2) == [values[1, 2], 3]
+    assert bitmap.page_by_rank(values.size, 2) == [[], nil]
+
+    assert_raises(ArgumentError) { bitmap.page(limit: 0) }
+    assert_raises(ArgumentError) { bitmap.page(after: 1) }
+    assert_raises(ArgumentError) { bitmap.page(after: 1, limit: 1) }
+    assert_raises(ArgumentError) { bitmap.page(after: "#{after_first}#{after_first}", limit: 1) }
+    assert_raises(ArgumentError) { bitmap.page(limit: 1, before: 1) }
+  end
+
+  def test_serde
+    bitmap = bitmap_class.from_a(values)
+    assert bitmap.eql?(bitmap_class.deserialize(bitmap.serialize))
+    assert bitmap.eql?(Marshal.load(Marshal.dump(bitmap)))
+  end
+
+  def test_deserialize_corrupt_data
+    ["not a bitmap", "\x01\x00"].each do |data|
+      error = assert_raises(Roaring::DeserializationError) { bitmap_class.deserialize(data) }
+      assert_kind_of Roaring::Error, error
+      refute_empty error.message
+    end
+  end
+
+  def test_from_a_invalid_values
+    assert_raises(Roaring::InvalidValueError) { bitmap_class.from_a([1, -1]) }
+    assert_raises(Roaring::InvalidValueError) { bitmap_class.from_a([max_value + 1]) }
+    assert_raises(Roaring::InvalidValueError) { bitmap_class.new.insert_many([1, "2"]) }
+  end
+
+  def test_frozen
+    bitmap = bitmap_class.from_a(values).freeze
+    other = bitmap_class.from_a([values.last, 3])
+
+    assert bitmap.contains(values.last)
+    assert bitmap.rank(values[1]) == 2
+    assert (bitmap | other).to_a == (values + [3]).sort
+    assert bitmap.each.to_a == values
+
+    [
+      -> { bitmap.insert(3) },
+      -> { bitmap.insert_many([3]) },
+      -> { bitmap.remove(values.first) },
+      -> { bitmap.insert_range(3..4) },
+      -> { bitmap.remove_range(0..2) },
+      -> { bitmap.clear },
+      -> { bitmap.union!(other) },
+      -> { bitmap.difference!(bitmap) },
+    ].each do |mutation|
+      assert_raises(FrozenError) { mutation.call }
+    end
+    assert bitmap.to_a == values
+    assert Ractor.shareable?(Ractor.make_shareable(bitmap)) if defined?(Ractor)
+  end
+
+  def test_dup
+    bitmap = bitmap_class.from_a(values)
+    copy = bitmap.dup
+    copy.remove(values.first)
+    copy.insert(3)
+    assert bitmap.to_a == values
+    assert copy.to_a == ([3] + values.drop(1)).sort
+    assert bitmap.freeze.clone.frozen?
+  end
+
+  def test_new_with_source
+    assert bitmap_class.new(nil).empty?
+    assert bitmap_class.new(values.reverse).to_a == values
+    assert bitmap_class.new(1...4).to_a == [1, 2, 3]
+    assert bitmap_class.new((max_value - 1)..).to_a == [max_value - 1, max_value]
+    assert bitmap_class.new(Set.new(values)).to_a == values
+    assert bitmap_class.new(bitmap_class.from_a(values)).to_a == values
+    assert bitmap_class.new(values.lazy.map { |i| i - 1 }).to_a == values.map { |i| i - 1 }
+
+    assert_raises(Roaring::InvalidValueError) { bitmap_class.new([-1]) }
+    assert_raises(TypeError) { bitmap_class.new(1) }
+
+    subclass = Class.new(bitmap_class)
+    assert_instance_of subclass, subclass.new([1]) | subclass.new([2])
+  end
+
+  def test_serialize_to_and_deserialize_from
+    bitmap = bitmap_class.from_a(values)
+    bitmap.insert_range(100_000...200_000)
+
+    io = StringIO.new(+"")
+    assert bitmap.serialize_to(io) == bitmap.serialize.bytesize
+    assert io.string == bitmap.serialize
+
+    io.write("trailer")
+    io.rewind
+    assert bitmap.eql?(bitmap_class.deserialize_from(io))
+    assert io.read == "trailer"
+  end
+
+  def test_serialize_compressed
+    bitmap = bitmap_class.from_a((0...1_000_000).step(7).to_a)
+    bitmap.insert_many(values)
+    plain = bitmap.serialize
+
+    [{ compression: :zstd }, { compression: :zstd, level: 19 }, { compression: :lz4 }].each do |options|
+      data = bitmap.serialize(**options)
+      assert data.start_with?("ROAR")
+      assert data.bytesize < plain.bytesize
+      assert bitmap.eql?(bitmap_class.deserialize(data))
+      assert bitmap.eql?(bitmap_class.deserialize(data.dup.freeze))
+      assert bitmap.eql?(bitmap_class.deserialize_from(StringIO.new(data)))
+    end
+    assert bitmap.serialize == plain
+  end
+
+  def test_serialize_checksum
+    bitmap = bitmap_class.from_a(values)
+    [{ checksum: true }, { checksum: true, compression: :zstd }].each do |options|
+      data = bitmap.serialize(**options)
+      assert data.start_with?("ROAR")
+      assert bitmap.eql?(bitmap_class.deserialize(data))
+      assert bitmap.eql?(bitmap_class.deserialize_from(StringIO.new(data)))
+
+      data.setbyte(-1, data.getbyte(-1) ^ 1)
+      error = assert_raises(Roaring::ChecksumError) { bitmap_class.deserialize(data) }
+      assert_kind_of Roaring::DeserializationError, error
+      assert_raises(Roaring::ChecksumError) { bitmap_class.deserialize_from(StringIO.new(data)) }
+    end
+    assert bitmap.eql?(Marshal.load(Marshal.dump(bitmap)))
+  end
+
+  def test_clickhouse
+    small = bitmap_class.from_a(values)
+    state = small.to_clickhouse
+    assert state.getbyte(0) == 0
+    assert small.eql?(bitmap_class.from_clickhouse(state))
+    assert bitmap_class.new.to_clickhouse == "\x00\x00".b
+
+    large = bitmap_class.from_a((0...100_000).step(3).to_a + values)
+    state = large.to_clickhouse
+    assert state.getbyte(0) == 1
+    assert large.eql?(bitmap_class.from_clickhouse(state))
+    assert bitmap_class.from_a((0..32).to_a).to_clickhouse.getbyte(0) == 1
+
+    ["", "\x02", "\x00\x21", state[0...-1]].each do |invalid|
+      assert_raises(Roaring::DeserializationError) { bitmap_class.from_clickhouse(invalid.b) }
+    end
+  end
+
+  def test_interval_string
+    bitmap = bitmap_class.from_a(values)
+    assert bitmap.to_interval_string == values.join(",")
+    assert bitmap_class.parse(values.join(", ")).eql?(bitmap)
+    assert bitmap_class.new.to_interval_string == ""
+
+    error = assert_raises(Roaring::ParseError) { bitmap_class.parse("1,#{max_value + 1}") }
+    assert error.message == "integer #{max_value + 1} is out of range at offset 2"
+    assert error.offset == 2
+  end
+
+  def test_to_interval_string_io
+    bitmap = bitmap_class.from_a((0...100_000).step(3).to_a + values)
+    io = StringIO.new(+"")
+    assert bitmap.to_interval_string(io) == io.string.bytesize
+    assert io.string == bitmap.to_interval_string
+    assert bitmap_class.parse(io.string).eql?(bitmap)
+    assert_raises(IOError) { bitmap.to_interval_string(StringIO.new.tap(&:close_write)) }
+  end
+
+  def test_inspect
+    bitmap = bitmap_class.from_a(values)
+    assert bitmap.inspect == "#<#{bitmap_class} cardinality=#{values.size} range=#{values.first}..#{values.last} " \
+      "bytes=#{bitmap.byte_size} [#{values.join(", ")}]>"
+    empty = bitmap_class.new
+    assert empty.inspect == "#<#{bitmap_class} cardinality=0 bytes=#{empty.byte_size} []>"
+
+    bitmap = bitmap_class.from_a((0..100).step(2).to_a + (1000..2000).to_a)
+    assert_match(/\A#<#{bitmap_class} cardinality=1052 range=0..2000 bytes=\d+ \[0, 2, 4, 6, 8, 10, 12, 14, 16, 18, ...\]>\z/, bitmap.inspect)
+    assert bitmap.pretty_inspect == "#{bitmap.inspect}\n"
+  end
+end
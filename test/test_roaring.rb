@@ -1,37 +1,87 @@
 # frozen_string_literal: true
 
 require "test_helper"
+require "shared_bitmap_tests"
+require "stringio"
+require "zlib"
 
 class TestRoaringBitmap < Minitest::Test
+  include SharedBitmapTests
+
+  def bitmap_class
+    Roaring::Bitmap
+  end
+
+  def max_value
+    2**32 - 1
+  end
+
+  def values
+    [1, 5, 70_000, 2**31, 2**32 - 1]
+  end
+
   def test_that_it_has_a_version_number
     refute_nil ::Roaring::VERSION
   end
 
-  def test_insert
+  def test_insert_range
     bitmap = Roaring::Bitmap.new
-    assert bitmap.insert(1) == true
-    assert bitmap.insert(1) == false
+    assert bitmap.insert_range(1..3) == 3
+    assert bitmap.insert_range(2...5) == 2
+    assert bitmap.to_a == [1, 2, 3, 4]
+
+    assert bitmap.insert_range(5...5) == 0
+    assert bitmap.insert_range((2**32 - 2)..) == 2
+    assert bitmap.max == 2**32 - 1
+  end
+
+  def test_ranges_ending_before_zero
+    bitmap = Roaring::Bitmap.from_a([0, 1, 5])
+    [0...0, 0..-1, 0...-1, 5..-3, 5...-(2**70), ..-1].each do |range|
+      assert bitmap.insert_range(range) == 0
+      assert bitmap.remove_range(range) == 0
+      assert bitmap.contains_range?(range)
+      assert bitmap.range_cardinality(range) == 0
+    end
+    assert bitmap.to_a == [0, 1, 5]
+    assert_raises(RangeError) { bitmap.insert_range(-1..-3) }
+
+    assert Roaring::Bitmap64.new(0..-1).empty?
+    assert Roaring::Bitmap64.new(0...0).empty?
   end
 
-  def test_insert_out_of_range
+  def test_insert_range_out_of_range
     bitmap = Roaring::Bitmap.new
     assert_raises(RangeError) do
-      bitmap.insert(2**64)
+      bitmap.insert_range(0..2**32)
     end
+    assert bitmap.insert_range((2**32 - 1)...2**32) == 1
   end
 
-  def test_remove
-    bitmap = Roaring::Bitmap.new
-    bitmap.insert(1)
-    assert bitmap.remove(1) == true
-    assert bitmap.remove(1) == false
+  def test_remove_range
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3, 4, 5])
+    assert bitmap.remove_range(2...4) == 2
+    assert bitmap.to_a == [1, 4, 5]
+    assert bitmap.remove_range(..4) == 2
+    assert bitmap.to_a == [5]
   end
 
-  def test_contains
+  def test_contains_range?
     bitmap = Roaring::Bitmap.new
-    bitmap.insert(1)
-    assert bitmap.contains(1) == true
-    assert bitmap.contains(2) == false
+    bitmap.insert_range(1..10)
+    assert bitmap.contains_range?(1..10)
+    assert bitmap.contains_range?(2...11)
+    refute bitmap.contains_range?(0..10)
+    refute bitmap.contains_range?(5..)
+    assert bitmap.contains_range?(20...20)
+  end
+
+  def test_range_cardinality
+    bitmap = Roaring::Bitmap.from_a([1, 5, 10, 20])
+    assert bitmap.range_cardinality(5..10) == 2
+    assert bitmap.range_cardinality(5...10) == 1
+    assert bitmap.range_cardinality(6..) == 2
+    assert bitmap.range_cardinality(30..40) == 0
   end
 
   def test_to_a
@@ -219,12 +269,69 @@ class TestRoaringBitmap < Minitest::Test
     assert bitmap.symmetric_difference_len(bitmap2) == 2
   end
 
-  def test_rank
-    bitmap = Roaring::Bitmap.new
-    bitmap.insert(1)
-    bitmap.insert(2)
-    bitmap.insert(3)
-    assert bitmap.rank(2) == 2
+  def test_inplace_operations
+    bitmap2 = Roaring::Bitmap.from_a([3, 4, 5])
+
+    assert Roaring::Bitmap.from_a([1, 2, 3]).intersection!(bitmap2).to_a == [3]
+    assert Roaring::Bitmap.from_a([1, 2, 3]).difference!(bitmap2).to_a == [1, 2]
+    assert Roaring::Bitmap.from_a([1, 2, 3]).symmetric_difference!(bitmap2).to_a == [1, 2, 4, 5]
+
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    assert bitmap.union!(bitmap2).equal?(bitmap)
+    assert bitmap.to_a == [1, 2, 3, 4, 5]
+    assert bitmap2.to_a == [3, 4, 5]
+  end
+
+  def test_inplace_operations_with_self
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    assert bitmap.union!(bitmap).to_a == [1, 2, 3]
+    assert bitmap.intersection!(bitmap).to_a == [1, 2, 3]
+    assert bitmap.symmetric_difference!(bitmap).to_a == []
+
+    bitmap.insert_many([1, 2, 3])
+    assert bitmap.difference!(bitmap).to_a == []
+  end
+
+  def test_inplace_operation_aliases
+    acc = Roaring::Bitmap.new
+    [[1, 2], [2, 3], [9]].each { |values| acc.or!(Roaring::Bitmap.from_a(values)) }
+    assert acc.to_a == [1, 2, 3, 9]
+
+    acc.and_not!(Roaring::Bitmap.from_a([9]))
+    acc.xor!(Roaring::Bitmap.from_a([3, 4]))
+    acc.and!(Roaring::Bitmap.from_a([1, 4]))
+    assert acc.to_a == [1, 4]
+  end
+
+  def test_union_all
+    bitmaps = [[1, 2], [2, 3], [5]].map { |values| Roaring::Bitmap.from_a(values) }
+    assert Roaring::Bitmap.union_all(bitmaps).to_a == [1, 2, 3, 5]
+    assert Roaring::Bitmap.union_all_len(bitmaps) == 4
+    assert Roaring::Bitmap.union_all([]).empty?
+  end
+
+  def test_intersection_all
+    bitmaps = [[1, 2, 3], [2, 3], [3, 4]].map { |values| Roaring::Bitmap.from_a(values) }
+    assert Roaring::Bitmap.intersection_all(bitmaps).to_a == [3]
+    assert Roaring::Bitmap.intersection_all_len(bitmaps) == 1
+  end
+
+  def test_xor_all
+    bitmaps = [[1, 2, 3], [2, 3], [3, 4]].map { |values| Roaring::Bitmap.from_a(values) }
+    assert Roaring::Bitmap.xor_all(bitmaps).to_a == [1, 3, 4]
+    assert Roaring::Bitmap.xor_all_len(bitmaps) == 3
+  end
+
+  def test_aggregates_with_repeated_bitmap
+    bitmap = Roaring::Bitmap.from_a([1, 2])
+    assert Roaring::Bitmap.union_all([bitmap, bitmap]).to_a == [1, 2]
+    assert Roaring::Bitmap.xor_all([bitmap, bitmap]).empty?
+  end
+
+  def test_aggregates_reject_non_bitmaps
+    assert_raises(TypeError) do
+      Roaring::Bitmap.union_all([Roaring::Bitmap.new, [1, 2]])
+    end
   end
 
   def test_insert_many
@@ -238,14 +345,6 @@ class TestRoaringBitmap < Minitest::Test
     assert bitmap.to_a == [1, 2, 3]
   end
 
-  def test_each
-    bitmap = Roaring::Bitmap.new
-    bitmap.insert_many([1, 2, 3])
-    ary = []
-    bitmap.each { |i| ary << i }
-    assert ary == [1, 2, 3]
-  end
-
   def test_each_with_no_block
     bitmap = Roaring::Bitmap.new
     bitmap.insert_many([1, 2, 3])
@@ -253,6 +352,257 @@ class TestRoaringBitmap < Minitest::Test
     assert ary == [1, 4, 9]
   end
 
+  def test_each_enumerator_size
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    enum = bitmap.each
+    assert enum.size == 3
+    bitmap.insert_range(10..19)
+    assert enum.size == 13
+    assert bitmap.each(snapshot: true).size == 13
+    assert Roaring::ConcurrentBitmap.from_a([1, 2]).each.size == 2
+  end
+
+  def test_each_break
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3, 4])
+    assert bitmap.each { |i| break i * 10 if i == 3 } == 30
+    bitmap.insert(5)
+    assert bitmap.each_with_index.to_a == [[1, 0], [2, 1], [3, 2], [4, 3], [5, 4]]
+    assert bitmap.each_slice(2).to_a == [[1, 2], [3, 4], [5]]
+  end
+
+  def test_reverse_each
+    bitmap = Roaring::Bitmap.from_a([1, 5, 70_000, 2**32 - 1])
+    seen = []
+    assert bitmap.reverse_each { |i| seen << i }.equal?(bitmap)
+    assert seen == [2**32 - 1, 70_000, 5, 1]
+    assert bitmap.reverse_each.to_a == seen
+    assert bitmap.reverse_each.size == 4
+    assert bitmap.reverse_each.first(2) == [2**32 - 1, 70_000]
+    assert Roaring::Bitmap.new.reverse_each.to_a == []
+  end
+
+  def test_each_from
+    bitmap = Roaring::Bitmap.from_a([1, 5, 70_000, 200_000])
+    assert bitmap.each_from(5).to_a == [5, 70_000, 200_000]
+    assert bitmap.each_from(6).to_a == [70_000, 200_000]
+    assert bitmap.each_from(0).to_a == bitmap.to_a
+    assert bitmap.each_from(200_001).to_a == []
+    seen = []
+    assert bitmap.each_from(70_000) { |i| seen << i }.equal?(bitmap)
+    assert seen == [70_000, 200_000]
+    assert_raises(RangeError) { bitmap.each_from(2**32) {} }
+  end
+
+  def test_each_before
+    bitmap = Roaring::Bitmap.from_a([1, 5, 70_000, 200_000])
+    assert bitmap.each_before(200_000).to_a == [70_000, 5, 1]
+    assert bitmap.each_before(200_001).to_a == [200_000, 70_000, 5, 1]
+    assert bitmap.each_before(5).to_a == [1]
+    assert bitmap.each_before(1).to_a == []
+    assert bitmap.each_before(0).to_a == []
+
+    cursor = 2**32 - 1
+    pages = []
+    loop do
+      page = bitmap.each_before(cursor).first(3)
+      break if page.empty?
+      pages << page
+      cursor = page.last
+    end
+    assert pages == [[200_000, 70_000, 5], [1]]
+  end
+
+  def test_each_in
+    bitmap = Roaring::Bitmap.from_a([1, 5, 70_000, 200_000, 2**32 - 1])
+    assert bitmap.each_in(5..70_000).to_a == [5, 70_000]
+    assert bitmap.each_in(5...70_000).to_a == [5]
+    assert bitmap.each_in(2..).to_a == [5, 70_000, 200_000, 2**32 - 1]
+    assert bitmap.each_in(..5).to_a == [1, 5]
+    assert bitmap.each_in(6..69_999).to_a == []
+    assert bitmap.each_in(10..5).to_a == []
+    assert bitmap.each_in(0...0).to_a == []
+    assert_raises(TypeError) { bitmap.each_in(5) {} }
+  end
+
+  # Cursors beyond the 32-bit integers, taken from a Bitmap64, read an empty page.
+  def test_page_cursor_beyond_32_bits
+    [[2**32 - 1, 2**40], [2**40, 2**41]].each do |values|
+      _, cursor = Roaring::Bitmap64.from_a(values).page(limit: 1)
+      assert Roaring::Bitmap.from_a([2**32 - 1]).page(after: cursor, limit: 1) == [[], nil]
+    end
+  end
+
+  def test_page_invalid_cursor
+    bitmap = Roaring::Bitmap.from_a([1, 5, 9])
+    _, cursor = bitmap.page(limit: 1)
+
+    error = assert_raises(ArgumentError) { bitmap.page(after: 5, limit: 1) }
+    assert error.message == "invalid cursor 5"
+    ["", "not a cursor", "#{cursor}=", "#{cursor}A", cursor[0..-2], "Ag#{cursor[2..]}", :AQAAAAAAAAAF].each do |after|
+      assert_raises(ArgumentError) { bitmap.page(after: after, limit: 1) }
+    end
+  end
+
+  def test_page_by_rank
+    bitmap = Roaring::Bitmap.from_a([1, 5, 9, 12, 20])
+    assert bitmap.page_by_rank(0, 2) == [[1, 5], 2]
+    assert bitmap.page_by_rank(2, 2) == [[9, 12], 4]
+    assert bitmap.page_by_rank(3, 2) == [[12, 20], nil]
+    assert bitmap.page_by_rank(4, 2) == [[20], nil]
+    assert bitmap.page_by_rank(5, 2) == [[], nil]
+    assert bitmap.page_by_rank(2**40, 2) == [[], nil]
+    assert Roaring::Bitmap.new.page_by_rank(0, 2) == [[], nil]
+    assert_raises(ArgumentError) { bitmap.page_by_rank(0, 0) }
+
+    bitmap = Roaring::Bitmap.from_a((0...100_000).step(7).to_a)
+    assert bitmap.page_by_rank(1000, 100).first == bitmap.to_a[1000, 100]
+  end
+
+  def test_next_and_prev_value
+    bitmap = Roaring::Bitmap.from_a([3, 4, 5, 10, 70_000])
+    assert bitmap.next_value(0) == 3
+    assert bitmap.next_value(4) == 4
+    assert bitmap.next_value(6) == 10
+    assert bitmap.next_value(11) == 70_000
+    assert bitmap.next_value(70_001).nil?
+    assert bitmap.prev_value(2**32 - 1) == 70_000
+    assert bitmap.prev_value(69_999) == 10
+    assert bitmap.prev_value(4) == 4
+    assert bitmap.prev_value(2).nil?
+    assert Roaring::Bitmap.new.next_value(0).nil?
+    assert Roaring::Bitmap.new.prev_value(2**32 - 1).nil?
+    assert_raises(RangeError) { bitmap.next_value(-1) }
+  end
+
+  def test_next_and_prev_absent
+    bitmap = Roaring::Bitmap.from_a([0, 1, 3, 4, 5, 10])
+    bitmap.insert_range(65_530..70_000)
+    assert bitmap.next_absent(0) == 2
+    assert bitmap.next_absent(2) == 2
+    assert bitmap.next_absent(3) == 6
+    assert bitmap.next_absent(65_530) == 70_000
+    assert bitmap.prev_absent(5) == 2
+    assert bitmap.prev_absent(69_999) == 65_529
+    assert bitmap.prev_absent(1).nil?
+    assert bitmap.prev_absent(6) == 6
+
+    full = Roaring::Bitmap.new
+    full.insert_range(2**32 - 3..)
+    assert full.next_absent(2**32 - 2).nil?
+    assert full.prev_absent(2**32 - 1) == 2**32 - 4
+    assert Roaring::Bitmap.new.next_absent(7) == 7
+  end
+
+  def test_nearest
+    bitmap = Roaring::Bitmap.from_a([3, 4, 5, 10])
+    assert bitmap.nearest(7) == 5
+    assert bitmap.nearest(8) == 10
+    assert bitmap.nearest(4) == 4
+    assert bitmap.nearest(0) == 3
+    assert bitmap.nearest(2**32 - 1) == 10
+    assert Roaring::Bitmap.from_a([4, 10]).nearest(7) == 4
+    assert Roaring::Bitmap.new.nearest(7).nil?
+  end
+
+  def test_seeking_iteration_modification
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    assert_raises(Roaring::ConcurrentModificationError) { bitmap.reverse_each { |i| bitmap.remove(i) } }
+    assert_raises(Roaring::ConcurrentModificationError) { bitmap.each_from(2) { |i| bitmap.remove(i) } }
+    assert_raises(Roaring::ConcurrentModificationError) { bitmap.each_before(3) { |i| bitmap.remove(i) } }
+    assert_raises(Roaring::ConcurrentModificationError) { bitmap.each_in(1..2) { |i| bitmap.remove(i) } }
+    assert bitmap.to_a == [1, 2, 3]
+
+    concurrent = Roaring::ConcurrentBitmap.from_a([1, 2, 3])
+    concurrent.reverse_each { |i| concurrent.remove(i) if i.odd? }
+    assert concurrent.to_a == [2]
+  end
+
+  def test_modifying_during_each_raises
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    assert_raises(Roaring::Error) do
+      bitmap.each { |i| bitmap.remove(i) }
+    end
+    assert bitmap.to_a == [1, 2, 3]
+
+    bitmap.insert(4)
+    assert bitmap.to_a == [1, 2, 3, 4]
+  end
+
+  def test_modifying_during_each_raises_concurrent_modification_error
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3, 4, 5])
+    error = assert_raises(Roaring::ConcurrentModificationError) do
+      bitmap.each { |i| bitmap.remove(i) if i.odd? }
+    end
+    assert_kind_of Roaring::Error, error
+    assert error.message == "can't modify bitmap during iteration"
+  end
+
+  def test_reading_during_each
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    other = Roaring::Bitmap.from_a([2])
+    seen = bitmap.each.map { |i| [bitmap.contains(i), (bitmap & other).to_a] }
+    assert seen == [[true, [2]]] * 3
+  end
+
+  def test_large_operations_across_threads
+    bitmap = Roaring::Bitmap.new
+    bitmap.insert_range(0...1_000_000)
+    bitmap2 = Roaring::Bitmap.new
+    bitmap2.insert_range(500_000...2_000_000)
+
+    threads = 4.times.map do
+      Thread.new do
+        [
+          (bitmap | bitmap2).cardinality,
+          (bitmap2 & bitmap).cardinality,
+          Roaring::Bitmap.deserialize(bitmap.serialize).cardinality,
+          Roaring::Bitmap.union_all([bitmap, bitmap2, bitmap]).cardinality
+        ]
+      end
+    end
+    assert threads.map(&:value).uniq == [[2_000_000, 500_000, 1_000_000, 2_000_000]]
+  end
+
+  def test_large_inplace_operations_across_threads
+    bitmap = Roaring::Bitmap.new
+    bitmap.insert_range(0...1_000_000)
+    bitmap2 = Roaring::Bitmap.new
+    bitmap2.insert_range(1_000_000...2_000_000)
+
+    threads = [
+      Thread.new { 4.times { bitmap.union!(bitmap2) } },
+      Thread.new { 4.times { bitmap2.union!(bitmap) } }
+    ]
+    threads.each(&:join)
+    assert bitmap.cardinality == 2_000_000
+    assert bitmap2.cardinality == 2_000_000
+  end
+
+  class Interrupted < StandardError; end
+
+  def test_interrupting_threads_waiting_for_a_lock
+    bitmap = Roaring::Bitmap.new
+    bitmap.insert_range(0...2_000_000)
+    other = Roaring::Bitmap.from_a((0...2_000_000).step(3).to_a)
+    done = false
+
+    writer = Thread.new do
+      bitmap.xor!(other) until done
+    end
+    readers = 4.times.map do
+      Thread.new do
+        loop { bitmap.cardinality }
+      end
+    end
+
+    sleep 0.1
+    readers.each { |reader| reader.raise(Interrupted) }
+    readers.each { |reader| assert_raises(Interrupted) { reader.join } }
+    done = true
+    writer.join
+    assert [2_000_000, 2_000_000 - other.cardinality].include?(bitmap.cardinality)
+  end
+
   def test_eql?
     bitmap = Roaring::Bitmap.new
     bitmap.insert_many([1, 2, 3])
@@ -261,11 +611,11 @@ class TestRoaringBitmap < Minitest::Test
     assert bitmap.eql?(bitmap2)
   end
 
-  def test_serde
-    bitmap = Roaring::Bitmap.new
-    bitmap.insert_many([1, 2, 3])
-    bitmap2 = Marshal.load(Marshal.dump(bitmap))
-    assert bitmap.eql?(bitmap2)
+  def test_deserialize_truncated_data
+    bytes = Roaring::Bitmap.from_a([1, 2, 3]).serialize
+    assert_raises(Roaring::DeserializationError) do
+      Roaring::Bitmap.deserialize(bytes[0, bytes.bytesize - 2])
+    end
   end
 
   def test_positional_accessors
@@ -279,4 +629,266 @@ class TestRoaringBitmap < Minitest::Test
       assert bitmap.send(accessor, 2) == 18
     end
   end
+
+  def test_shareable_across_ractors
+    skip "Ractor is not available" unless defined?(Ractor)
+
+    bitmap = Ractor.make_shareable(Roaring::Bitmap.from_a([1, 2, 3]))
+    assert Ractor.shareable?(bitmap)
+
+    ractor = Ractor.new(bitmap) { |rb| [rb.contains(2), rb.cardinality, rb.to_a] }
+    result = ractor.respond_to?(:value) ? ractor.value : ractor.take
+    assert result == [true, 3, [1, 2, 3]]
+  end
+
+  def test_dup_keeps_class
+    assert_instance_of Roaring::ConcurrentBitmap, Roaring::ConcurrentBitmap.new.dup
+  end
+
+  def test_allocator_survives_class_lookups
+    # Failed conversions and snapshot enumerators look up the class of the wrapped type.
+    assert_raises(TypeError) { Roaring::Bitmap.new.union(1) }
+    Roaring::Bitmap.from_a([1]).each(snapshot: true).to_a
+
+    assert Roaring::Bitmap.new([1]).dup.to_a == [1]
+    assert Roaring::ConcurrentBitmap.new([2]).to_a == [2]
+  end
+
+  def test_clone_frozen_state
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3]).freeze
+
+    refute bitmap.dup.frozen?
+    assert bitmap.clone.frozen?
+    assert bitmap.clone.to_a == [1, 2, 3]
+
+    copy = bitmap.clone(freeze: false)
+    refute copy.frozen?
+    copy.insert(4)
+    assert copy.to_a == [1, 2, 3, 4]
+    assert bitmap.to_a == [1, 2, 3]
+  end
+
+  class SegmentBitmap < Roaring::Bitmap
+  end
+
+  def test_subclass
+    bitmap = SegmentBitmap.new([1, 2])
+    other = SegmentBitmap.new([2, 3])
+
+    assert_instance_of SegmentBitmap, bitmap
+    assert_instance_of SegmentBitmap, SegmentBitmap.from_a([1])
+    assert_instance_of SegmentBitmap, SegmentBitmap.full
+    assert_instance_of SegmentBitmap, SegmentBitmap.deserialize(bitmap.serialize)
+    assert_instance_of SegmentBitmap, Marshal.load(Marshal.dump(bitmap))
+    assert_instance_of SegmentBitmap, SegmentBitmap.union_all([bitmap, other])
+    [bitmap | other, bitmap & other, bitmap - other, bitmap ^ other].each do |result|
+      assert_instance_of SegmentBitmap, result
+    end
+  end
+
+  def test_serialize_to_gzip
+    bitmap = Roaring::Bitmap.from_a((0...100_000).step(3).to_a)
+    io = StringIO.new(+"")
+    gzip = Zlib::GzipWriter.new(io)
+    bitmap.serialize_to(gzip)
+    gzip.finish
+
+    gunzip = Zlib::GzipReader.new(StringIO.new(io.string))
+    assert bitmap.eql?(Roaring::Bitmap.deserialize_from(gunzip))
+  end
+
+  def test_deserialize_from_truncated_io
+    io = StringIO.new(Roaring::Bitmap.from_a([1, 2, 3]).serialize[0...-2])
+    assert_raises(Roaring::DeserializationError) do
+      Roaring::Bitmap.deserialize_from(io)
+    end
+  end
+
+  def test_serialize_to_propagates_io_errors
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    assert_raises(IOError) { bitmap.serialize_to(StringIO.new.tap(&:close_write)) }
+
+    writer = Object.new
+    writer.define_singleton_method(:write) { |_| bitmap.insert(4) }
+    assert_raises(Roaring::ConcurrentModificationError) { bitmap.serialize_to(writer) }
+    bitmap.insert(4)
+    assert bitmap.to_a == [1, 2, 3, 4]
+  end
+
+  def test_serialize_compression_options
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    assert_raises(ArgumentError) { bitmap.serialize(compression: :gzip) }
+    assert_raises(ArgumentError) { bitmap.serialize(compression: :lz4, level: 1) }
+    assert_raises(ArgumentError) { bitmap.serialize(level: 1) }
+  end
+
+  def test_deserialize_corrupt_compressed_data
+    data = Roaring::Bitmap.from_a([1, 2, 3]).serialize(compression: :zstd)
+    assert_raises(Roaring::DeserializationError) do
+      Roaring::Bitmap.deserialize(data[0...-1])
+    end
+    assert_raises(Roaring::DeserializationError) do
+      Roaring::FrozenBitmap.view(data.freeze)
+    end
+  end
+
+  def test_deserialize_from_compressed_io
+    bitmap = Roaring::Bitmap.from_a((0...100_000).step(3).to_a)
+    io = StringIO.new(bitmap.serialize(compression: :lz4) + "trailer")
+    assert bitmap.eql?(Roaring::Bitmap.deserialize_from(io))
+    assert io.read == "trailer"
+  end
+
+  def test_serialize_checksum_cardinality
+    data = Roaring::Bitmap.from_a([1, 2, 3]).serialize(checksum: true)
+    data[24, 8] = [4].pack("Q<")
+    assert_raises(Roaring::ChecksumError) { Roaring::Bitmap.deserialize(data) }
+  end
+
+  def test_deserialize_forged_decompressed_size
+    bitmap = Roaring::Bitmap.from_a((0...100_000).step(3).to_a)
+    plain_size = bitmap.serialize.bytesize
+    %i[zstd lz4].each do |compression|
+      data = bitmap.serialize(compression: compression)
+      [2**62, 2**32 + 1, plain_size - 1, plain_size + 1].each do |size|
+        forged = data.dup
+        forged[16, 8] = [size].pack("Q<")
+        assert_raises(Roaring::DeserializationError) { Roaring::Bitmap.deserialize(forged) }
+        assert_raises(Roaring::DeserializationError) { Roaring::Bitmap.deserialize_from(StringIO.new(forged)) }
+      end
+    end
+  end
+
+  def test_deserialize_forged_lz4_size
+    bitmap = Roaring::Bitmap.from_a((0...100_000).step(3).to_a)
+    data = bitmap.serialize(compression: :lz4)
+    payload_size = data[8, 8].unpack1("Q<")
+    # More than the payload can hold, more than any 32-bit bitmap, and 4 GiB for a Bitmap64.
+    [[Roaring::Bitmap, payload_size * 255 + 1], [Roaring::Bitmap, 600 * 2**20], [Roaring::Bitmap64, 2**32]].each do |klass, size|
+      forged = data.dup
+      forged[16, 8] = [size].pack("Q<")
+      error = assert_raises(Roaring::DeserializationError) { klass.deserialize(forged) }
+      assert error.message.include?(size.to_s)
+    end
+  end
+
+  def test_marshal_validates_data
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    dumped = Marshal.dump(bitmap)
+    assert_includes dumped, "ROAR"
+
+    corrupt = dumped.sub(bitmap.serialize) { |data| data.tr("\x01", "\x09") }
+    refute_equal dumped, corrupt
+    assert_raises(Roaring::ChecksumError) { Marshal.load(corrupt) }
+  end
+
+  def test_clickhouse_small_set
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3])
+    state = "\x00\x03\x01\x00\x00\x00\x02\x00\x00\x00\x03\x00\x00\x00".b
+    assert bitmap.to_clickhouse == state
+    assert bitmap.eql?(Roaring::Bitmap.from_clickhouse(state))
+  end
+
+  def test_clickhouse_run_containers
+    # groupBitmapState(number) FROM numbers(100000), run-optimized by ClickHouse.
+    data = [0x0001303B, 0b11, 0, 0xFFFF, 1, 0x869F, 1, 0, 0xFFFF, 1, 0, 0x869F].pack("VCvvvvvvvvvv")
+    state = "\x01".b + [data.bytesize].pack("C") + data
+    bitmap = Roaring::Bitmap.from_clickhouse(state)
+    assert bitmap.cardinality == 100_000
+    assert bitmap.min == 0
+    assert bitmap.max == 99_999
+  end
+
+  def test_clickhouse_invalid_data
+    ["\x00\x01\x01\x00", "\x00\x01\x01\x00\x00\x00\x00"].each do |state|
+      assert_raises(Roaring::DeserializationError) { Roaring::Bitmap.from_clickhouse(state.b) }
+    end
+  end
+
+  def test_each_range
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3, 7, 10, 11, 65_535, 65_536])
+    ranges = []
+    assert bitmap.each_range { |range| ranges << range }.equal?(bitmap)
+    assert ranges == [1..3, 7..7, 10..11, 65_535..65_536]
+    assert bitmap.each_range.to_a == ranges
+    assert bitmap.each_range(pairs: true).to_a == [[1, 3], [7, 7], [10, 11], [65_535, 65_536]]
+    assert Roaring::Bitmap.new.each_range.to_a == []
+  end
+
+  def test_each_range_long_runs
+    bitmap = Roaring::Bitmap.new
+    bitmap.insert_range(0..)
+    bitmap.remove(2**31)
+    assert bitmap.to_ranges == [0..(2**31 - 1), (2**31 + 1)..(2**32 - 1)]
+  end
+
+  def test_each_range_modification
+    bitmap = Roaring::Bitmap.from_a([1, 2, 5])
+    assert_raises(Roaring::ConcurrentModificationError) do
+      bitmap.each_range { |range| bitmap.remove(range.first) }
+    end
+  end
+
+  def test_to_ranges
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3, 7])
+    assert bitmap.to_ranges == [1..3, 7..7]
+    assert bitmap.to_ranges(pairs: true) == [[1, 3], [7, 7]]
+  end
+
+  def test_from_ranges
+    bitmap = Roaring::Bitmap.from_ranges([1..3, [7, 7], 10...12, [20, 19], 30...30])
+    assert bitmap.to_a == [1, 2, 3, 7, 10, 11]
+    assert bitmap.eql?(Roaring::Bitmap.from_ranges(bitmap.to_ranges))
+    assert bitmap.eql?(Roaring::Bitmap.from_ranges(bitmap.to_ranges(pairs: true)))
+    assert_instance_of SegmentBitmap, SegmentBitmap.from_ranges([1..2])
+
+    assert_raises(TypeError) { Roaring::Bitmap.from_ranges([1]) }
+    assert_raises(TypeError) { Roaring::Bitmap.from_ranges([[1, 2, 3]]) }
+    assert_raises(RangeError) { Roaring::Bitmap.from_ranges([0..2**32]) }
+  end
+
+  def test_parse
+    bitmap = Roaring::Bitmap.parse("1-5,7,9-12")
+    assert bitmap.to_a == [1, 2, 3, 4, 5, 7, 9, 10, 11, 12]
+    assert Roaring::Bitmap.parse(" 9 - 12 ,\t7 , 1-5 ,3\n").eql?(bitmap)
+    assert Roaring::Bitmap.parse("").empty?
+    assert Roaring::Bitmap.parse("  ").empty?
+    assert Roaring::Bitmap.parse("0-4294967295").cardinality == 2**32
+    assert_instance_of SegmentBitmap, SegmentBitmap.parse("1")
+  end
+
+  def test_parse_errors
+    {
+      "1-5,x" => ["expected an integer, found 'x' at offset 4", 4],
+      "1-5," => ["expected an integer, found the end of the input at offset 4", 4],
+      "1 2" => ["expected \",\" or \"-\", found '2' at offset 2", 2],
+      "1,9-3" => ["range 9-3 ends before it starts at offset 2", 2],
+      "4294967296" => ["integer 4294967296 is out of range at offset 0", 0],
+      "-1" => ["expected an integer, found '-' at offset 0", 0],
+    }.each do |input, (message, offset)|
+      error = assert_raises(Roaring::ParseError, input) { Roaring::Bitmap.parse(input) }
+      assert error.message == message
+      assert error.offset == offset
+    end
+    assert Roaring::ParseError < Roaring::Error
+  end
+
+  def test_to_interval_string
+    assert Roaring::Bitmap.from_a([1, 2, 3, 4, 5, 7, 9, 10]).to_interval_string == "1-5,7,9-10"
+    assert Roaring::Bitmap.new.to_interval_string == ""
+
+    bitmap = Roaring::Bitmap.new
+    bitmap.insert_range(0..)
+    bitmap.remove(2**31)
+    assert bitmap.to_interval_string == "0-2147483647,2147483649-4294967295"
+    assert Roaring::Bitmap.parse(bitmap.to_interval_string).eql?(bitmap)
+  end
+
+  def test_inspect
+    bitmap = Roaring::Bitmap.from_a([1, 2, 3, 7, 9, 10])
+    assert bitmap.inspect == "#<Roaring::Bitmap cardinality=6 range=1..10 bytes=28 [1-3, 7, 9-10]>"
+    assert Roaring::Bitmap.new.inspect == "#<Roaring::Bitmap cardinality=0 bytes=8 []>"
+    assert SegmentBitmap.from_a([5]).inspect == "#<TestRoaringBitmap::SegmentBitmap cardinality=1 range=5..5 bytes=18 [5]>"
+  end
+
 end
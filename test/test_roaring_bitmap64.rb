@@ -0,0 +1,247 @@
+# frozen_string_literal: true
+
+require "test_helper"
+require "shared_bitmap_tests"
+require "stringio"
+
+class TestRoaringBitmap64 < Minitest::Test
+  include SharedBitmapTests
+
+  FIXTURES = File.expand_path("fixtures/portable64", __dir__)
+
+  def bitmap_class
+    Roaring::Bitmap64
+  end
+
+  def max_value
+    2**64 - 1
+  end
+
+  def values
+    [1, 5, 2**32 + 3, 2**40, 2**64 - 1]
+  end
+
+  def test_insert_full_u64_range
+    bitmap = Roaring::Bitmap64.new
+    bitmap.insert(0)
+    bitmap.insert(2**64 - 1)
+    assert bitmap.to_a == [0, 2**64 - 1]
+  end
+
+  def test_full_u64_range_rejected
+    assert_raises(RangeError) { Roaring::Bitmap64.new(0..) }
+    assert_raises(RangeError) { Roaring::Bitmap64.new(..(2**64 - 1)) }
+    assert_raises(RangeError) { Roaring::Bitmap64.from_ranges([[0, 2**64 - 1]]) }
+    assert_raises(RangeError) { Roaring::Bitmap64.parse("0-18446744073709551615") }
+    assert Roaring::Bitmap64.new((2**64 - 3)..).to_a == [2**64 - 3, 2**64 - 2, 2**64 - 1]
+  end
+
+  def test_cardinality_min_max
+    bitmap = Roaring::Bitmap64.from_a([2**50, 1, 2**40])
+    assert bitmap.cardinality == 3
+    assert bitmap.min == 1
+    assert bitmap.max == 2**50
+    assert bitmap.to_a == [1, 2**40, 2**50]
+  end
+
+  def test_set_algebra
+    bitmap = Roaring::Bitmap64.from_a([1, 2**40])
+    bitmap2 = Roaring::Bitmap64.from_a([2**40, 2**50])
+
+    assert (bitmap | bitmap2).to_a == [1, 2**40, 2**50]
+    assert (bitmap & bitmap2).to_a == [2**40]
+    assert (bitmap - bitmap2).to_a == [1]
+    assert (bitmap ^ bitmap2).to_a == [1, 2**50]
+
+    assert bitmap.union_len(bitmap2) == 3
+    assert bitmap.intersection_len(bitmap2) == 1
+    assert bitmap.difference_len(bitmap2) == 1
+    assert bitmap.symmetric_difference_len(bitmap2) == 2
+  end
+
+  def test_ranges_across_buckets
+    bitmap = Roaring::Bitmap64.new
+    assert bitmap.insert_range((2**32 - 2)..(2**32 + 1)) == 4
+    assert bitmap.contains_range?((2**32 - 2)...(2**32 + 2))
+    refute bitmap.contains_range?((2**32 - 3)..(2**32))
+    assert bitmap.range_cardinality(2**32..) == 2
+    assert bitmap.remove_range((2**32 - 1)..2**32) == 2
+    assert bitmap.to_a == [2**32 - 2, 2**32 + 1]
+
+    assert bitmap.insert_range((2**64 - 2)..) == 2
+    assert bitmap.max == 2**64 - 1
+    assert_raises(RangeError) { bitmap.insert_range(0..) }
+    assert_raises(RangeError) { bitmap.insert_range(0..2**64) }
+  end
+
+  def test_inplace_operations
+    bitmap = Roaring::Bitmap64.from_a([1, 2**40])
+    bitmap2 = Roaring::Bitmap64.from_a([2**40, 2**50])
+
+    assert bitmap.dup.intersection!(bitmap2).to_a == [2**40]
+    assert bitmap.dup.difference!(bitmap2).to_a == [1]
+    assert bitmap.dup.symmetric_difference!(bitmap2).to_a == [1, 2**50]
+    assert bitmap.union!(bitmap2).equal?(bitmap)
+    assert bitmap.to_a == [1, 2**40, 2**50]
+
+    acc = Roaring::Bitmap64.new
+    acc.or!(bitmap).and_not!(bitmap2).xor!(Roaring::Bitmap64.from_a([2**60]))
+    assert acc.and!(Roaring::Bitmap64.from_a([1, 2**60])).to_a == [1, 2**60]
+  end
+
+  def test_aggregates
+    bitmaps = [[1, 2**40], [2**40, 2**50], [2**40]].map { |values| Roaring::Bitmap64.from_a(values) }
+    assert Roaring::Bitmap64.union_all(bitmaps).to_a == [1, 2**40, 2**50]
+    assert Roaring::Bitmap64.union_all_len(bitmaps) == 3
+    assert Roaring::Bitmap64.intersection_all(bitmaps).to_a == [2**40]
+    assert Roaring::Bitmap64.intersection_all_len(bitmaps) == 1
+    assert Roaring::Bitmap64.xor_all(bitmaps).to_a == [1, 2**40, 2**50]
+    assert Roaring::Bitmap64.xor_all_len(bitmaps) == 3
+    assert Roaring::Bitmap64.union_all([]).empty?
+  end
+
+  def test_reverse_and_seeking_iteration
+    values = [1, 5, 2**32, 2**32 + 9, 2**40, 2**64 - 1]
+    bitmap = Roaring::Bitmap64.from_a(values)
+    assert bitmap.reverse_each.to_a == values.reverse
+    assert bitmap.reverse_each.size == 6
+    assert bitmap.each_from(6).to_a == [2**32, 2**32 + 9, 2**40, 2**64 - 1]
+    assert bitmap.each_from(2**32 + 1).first == 2**32 + 9
+    assert bitmap.each_before(2**40).to_a == [2**32 + 9, 2**32, 5, 1]
+    assert bitmap.each_before(0).to_a == []
+    assert bitmap.each_in(6..2**40).to_a == [2**32, 2**32 + 9, 2**40]
+    assert bitmap.each_in(2**32 + 1...2**40).to_a == [2**32 + 9]
+    assert bitmap.each_in(2**33..2**39).to_a == []
+    assert bitmap.each_in(2**40..).to_a == [2**40, 2**64 - 1]
+
+    (0..63).each do |shift|
+      start = 2**shift
+      assert bitmap.each_from(start).to_a == values.select { |v| v >= start }
+      assert bitmap.each_before(start).to_a == values.select { |v| v < start }.reverse
+    end
+  end
+
+  # roaring 0.11.5 drops the last bucket when an iterator is sought from the front then the back,
+  # and forgets the back's bound when the back seek finds no bucket.
+  def test_each_in_across_buckets
+    values = [2**32 + 5, 2**33, 2**33 + 7, 2**34 - 1, 2**40]
+    bitmap = Roaring::Bitmap64.from_a(values)
+    bounds = [0, 5, 2**32, 2**32 + 5, 2**33 - 1, 2**33, 2**33 + 7, 2**34, 2**40, 2**41]
+
+    bounds.product(bounds).each do |first, last|
+      expected = values.select { |v| v >= first && v <= last }
+      assert bitmap.each_in(first..last).to_a == expected
+      assert bitmap.each_in(first...last).to_a == expected - [last]
+    end
+    assert bitmap.each_in(0..5).to_a == []
+    assert bitmap.each_in(2**40 + 1..).to_a == []
+  end
+
+  def test_page_across_buckets
+    bitmap = Roaring::Bitmap64.from_a([1, 2**32, 2**40, 2**64 - 1])
+    page, cursor = bitmap.page(limit: 2)
+    assert page == [1, 2**32]
+    assert bitmap.page(after: cursor, limit: 2) == [[2**40, 2**64 - 1], nil]
+    # The cursor after 2**64 - 1, which no page returns as it would be the last one.
+    assert bitmap.page(after: "Af__________", limit: 2) == [[], nil]
+    assert bitmap.page_by_rank(1, 2) == [[2**32, 2**40], 3]
+    assert bitmap.page_by_rank(3, 2) == [[2**64 - 1], nil]
+    assert_raises(ArgumentError) { bitmap.page(after: 2**32, limit: 1) }
+  end
+
+  def test_successors_and_predecessors
+    bitmap = Roaring::Bitmap64.from_ranges([0..1, (2**32 - 2)..(2**32 + 3), 2**40..2**40, (2**64 - 2)..])
+    assert bitmap.next_value(2) == 2**32 - 2
+    assert bitmap.next_value(2**32 + 4) == 2**40
+    assert bitmap.prev_value(2**40 - 1) == 2**32 + 3
+    assert bitmap.prev_value(2**64 - 3) == 2**40
+    assert bitmap.next_absent(2**32 - 2) == 2**32 + 4
+    assert bitmap.prev_absent(2**32 + 3) == 2**32 - 3
+    assert bitmap.next_absent(2**40) == 2**40 + 1
+    assert bitmap.next_absent(2**64 - 2).nil?
+    assert bitmap.prev_absent(1).nil?
+    assert bitmap.prev_absent(2**64 - 1) == 2**64 - 3
+    assert bitmap.nearest(2**39) == 2**32 + 3
+    assert bitmap.nearest(2**41) == 2**40
+    assert bitmap.nearest(0) == 0
+    assert Roaring::Bitmap64.new.nearest(5).nil?
+  end
+
+  def test_clickhouse_state
+    small = Roaring::Bitmap64.from_a([1, 2**40])
+    state = [0, 2, 1, 2**40].pack("CCQ<Q<")
+    assert small.to_clickhouse == state
+    assert small.eql?(Roaring::Bitmap64.from_clickhouse(state))
+
+    large = Roaring::Bitmap64.from_a((0...100).map { |i| i * 2**33 })
+    state = large.to_clickhouse
+    assert state.getbyte(0) == 1
+    assert large.eql?(Roaring::Bitmap64.from_clickhouse(state))
+  end
+
+  def fixture(name)
+    File.binread(File.join(FIXTURES, name))
+  end
+
+  def test_portable_fixtures
+    {
+      "empty.bin" => [],
+      "32bit_values.bin" => (0..9).to_a,
+      "spread_values.bin" => (0..9).flat_map { |i| (0..9).map { |j| i * 2**32 + j * 3 } }
+    }.each do |name, values|
+      data = fixture(name)
+      bitmap = Roaring::Bitmap64.deserialize(data)
+      assert bitmap.to_a == values, name
+      assert bitmap.serialize == data, name
+      assert bitmap.eql?(Roaring::Bitmap64.deserialize_from(StringIO.new(data))), name
+    end
+  end
+
+  def test_portable_run_containers
+    bitmap = Roaring::Bitmap64.deserialize(fixture("high_values.bin"))
+    assert bitmap.cardinality == 2**16
+    assert bitmap.min == 2**64 - 2**16
+    assert bitmap.max == 2**64 - 1
+
+    mixed = Roaring::Bitmap64.from_a(
+      (0...40_000).step(7).map { |v| 2**32 + v } +
+      (0...65_536).step(2).map { |v| 2**32 + 65_536 + v } +
+      (100...60_000).map { |v| 2**32 + 2 * 65_536 + v } +
+      [5, 6, 7, 1000, 1001].map { |v| 2**32 + 3 * 65_536 + v } +
+      (0...1000).map { |v| 2**40 + v } +
+      [2**63 + 1, 2**64 - 1]
+    )
+    data = fixture("mixed_containers.bin")
+    assert mixed.eql?(Roaring::Bitmap64.deserialize(data))
+
+    io = StringIO.new(data + "trailer")
+    assert mixed.eql?(Roaring::Bitmap64.deserialize_from(io))
+    assert io.read == "trailer"
+  end
+
+  def test_portable_invalid_fixtures
+    %w[unsorted_keys.bin truncated.bin].each do |name|
+      assert_raises(Roaring::DeserializationError, name) do
+        Roaring::Bitmap64.deserialize(fixture(name))
+      end
+      assert_raises(Roaring::DeserializationError, name) do
+        Roaring::Bitmap64.deserialize_from(StringIO.new(fixture(name)))
+      end
+    end
+  end
+
+  def test_ranges
+    bitmap = Roaring::Bitmap64.from_ranges([(2**32 - 2)..(2**32 + 1), [2**40, 2**40], (2**64 - 2)..])
+    assert bitmap.cardinality == 7
+    assert bitmap.to_ranges == [(2**32 - 2)..(2**32 + 1), (2**40)..(2**40), (2**64 - 2)..(2**64 - 1)]
+    assert bitmap.each_range(pairs: true).to_a == [[2**32 - 2, 2**32 + 1], [2**40, 2**40], [2**64 - 2, 2**64 - 1]]
+    assert bitmap.eql?(Roaring::Bitmap64.from_ranges(bitmap.to_ranges(pairs: true)))
+  end
+
+  def test_interval_string_across_buckets
+    bitmap = Roaring::Bitmap64.parse("4294967294-4294967297, 1099511627776, 18446744073709551614-18446744073709551615")
+    assert bitmap.to_ranges == [(2**32 - 2)..(2**32 + 1), (2**40)..(2**40), (2**64 - 2)..(2**64 - 1)]
+    assert bitmap.to_interval_string == "4294967294-4294967297,1099511627776,18446744073709551614-18446744073709551615"
+  end
+
+end
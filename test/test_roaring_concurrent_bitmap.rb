@@ -0,0 +1,105 @@
+# frozen_string_literal: true
+
+require "test_helper"
+require "stringio"
+
+class TestRoaringConcurrentBitmap < Minitest::Test
+  def test_new
+    bitmap = Roaring::ConcurrentBitmap.new
+    assert_instance_of Roaring::ConcurrentBitmap, bitmap
+    assert_kind_of Roaring::Bitmap, bitmap
+    assert bitmap.empty?
+  end
+
+  def test_modifying_during_each
+    bitmap = Roaring::ConcurrentBitmap.new
+    bitmap.insert_many([1, 2, 3, 4])
+    seen = []
+    bitmap.each do |i|
+      seen << i
+      bitmap.remove(i) if i.odd?
+      bitmap.insert(i + 10)
+    end
+    assert seen == [1, 2, 3, 4]
+    assert bitmap.to_a == [2, 4, 11, 12, 13, 14]
+  end
+
+  def test_concurrent_readers_and_writers
+    bitmap = Roaring::ConcurrentBitmap.new
+    bitmap.insert_range(0...10_000)
+
+    writers = 4.times.map do |t|
+      Thread.new do
+        1_000.times { |i| bitmap.insert(10_000 + t * 1_000 + i) }
+      end
+    end
+    readers = 4.times.map do
+      Thread.new do
+        count = 0
+        bitmap.each { |i| count += 1 if bitmap.contains(i) }
+        count
+      end
+    end
+
+    writers.each(&:join)
+    assert readers.map(&:value).all? { |count| count >= 10_000 }
+    assert bitmap.cardinality == 14_000
+  end
+
+  def test_each_range_while_writing
+    bitmap = Roaring::ConcurrentBitmap.from_ranges([1..3, 7..9])
+    seen = []
+    bitmap.each_range do |range|
+      seen << range
+      bitmap.insert(range.end + 1)
+    end
+    assert seen == [1..3, 7..9]
+    assert bitmap.to_ranges == [1..4, 7..10]
+  end
+
+  def test_serialize_to_while_writing
+    bitmap = Roaring::ConcurrentBitmap.from_a([1, 2, 3])
+    io = StringIO.new
+    io.define_singleton_method(:write) do |data|
+      bitmap.insert(4)
+      super(data)
+    end
+
+    bitmap.serialize_to(io)
+    assert Roaring::Bitmap.deserialize(io.string).to_a == [1, 2, 3]
+    assert bitmap.to_a == [1, 2, 3, 4]
+  end
+
+  def test_to_interval_string_while_writing
+    bitmap = Roaring::ConcurrentBitmap.from_ranges([1..3, 7..9])
+    io = StringIO.new
+    io.define_singleton_method(:write) do |data|
+      bitmap.insert(5)
+      super(data)
+    end
+
+    bitmap.to_interval_string(io)
+    assert io.string == "1-3,7-9"
+    assert bitmap.to_interval_string == "1-3,5,7-9"
+  end
+
+  def test_ractors
+    skip "Ractor is not available" unless defined?(Ractor)
+
+    shared = Ractor.make_shareable(Roaring::ConcurrentBitmap.from_a([1, 2, 3]))
+    assert Ractor.shareable?(shared)
+
+    ractors = 2.times.map do |i|
+      Ractor.new(shared, i) do |rb, offset|
+        own = Roaring::ConcurrentBitmap.new
+        rb.each { |value| own.insert(value + offset * 10) }
+        io = StringIO.new
+        own.serialize_to(io)
+        io.rewind
+        Roaring::ConcurrentBitmap.deserialize_from(io).to_a
+      end
+    end
+    results = ractors.map { |ractor| ractor.respond_to?(:value) ? ractor.value : ractor.take }
+    assert results == [[1, 2, 3], [11, 12, 13]]
+  end
+end
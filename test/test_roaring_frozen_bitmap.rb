@@ -0,0 +1,155 @@
+# frozen_string_literal: true
+
+require "test_helper"
+require "pathname"
+require "tempfile"
+
+class TestRoaringFrozenBitmap < Minitest::Test
+  def setup
+    @bitmap = Roaring::Bitmap.from_a([1, 5, 70_000, 2**32 - 1])
+    @bitmap.insert_range(100_000...110_000)
+    @frozen = Roaring::FrozenBitmap.view(@bitmap.serialize.freeze)
+  end
+
+  def test_queries
+    assert @frozen.cardinality == @bitmap.cardinality
+    assert @frozen.contains(70_000)
+    refute @frozen.contains(2)
+    assert @frozen.include?(2**32 - 1)
+    assert @frozen.min == 1
+    assert @frozen.max == 2**32 - 1
+    refute @frozen.empty?
+    [0, 1, 4, 5, 70_000, 105_000, 2**32 - 1].each do |i|
+      assert @frozen.rank(i) == @bitmap.rank(i)
+    end
+  end
+
+  def test_each
+    assert @frozen.to_a == @bitmap.to_a
+    assert @frozen.each.first(2) == [1, 5]
+    assert @frozen.each.size == @bitmap.cardinality
+  end
+
+  def test_set_operations
+    other = Roaring::Bitmap.from_a([5, 6])
+    assert_instance_of Roaring::Bitmap, @frozen | other
+    assert (@frozen & other).to_a == [5]
+    assert (@frozen - other).eql?(@bitmap - other)
+    assert (@frozen ^ other).eql?(@bitmap ^ other)
+    assert (@frozen & @frozen).eql?(@bitmap)
+    assert @frozen.to_bitmap.eql?(@bitmap)
+  end
+
+  def test_empty
+    frozen = Roaring::FrozenBitmap.view(Roaring::Bitmap.new.serialize.freeze)
+    assert frozen.empty?
+    assert_nil frozen.min
+    assert frozen.to_a == []
+  end
+
+  def test_requires_frozen_string
+    assert_raises(ArgumentError) do
+      Roaring::FrozenBitmap.view(+@bitmap.serialize)
+    end
+    assert_raises(NoMethodError) { Roaring::FrozenBitmap.new }
+  end
+
+  def test_invalid_data
+    assert_raises(Roaring::DeserializationError) do
+      Roaring::FrozenBitmap.view("garbage")
+    end
+    assert_raises(Roaring::DeserializationError) do
+      Roaring::FrozenBitmap.view(@bitmap.serialize[0...-1].freeze)
+    end
+  end
+
+  def test_verify
+    assert Roaring::FrozenBitmap.view(@bitmap.serialize.freeze, verify: true).to_a == @bitmap.to_a
+
+    # A run container holding the run 0xFFF0..0x1000F, which doesn't fit in 16 bits.
+    corrupt_run = ([12_347].pack("V") + "\x01".b + [0, 0x20, 1, 0xFFF0, 0x20].pack("v5")).freeze
+    # An array container whose values are out of order.
+    unsorted_array = ([12_346, 1].pack("V2") + [0, 1].pack("v2") + [16].pack("V") + [5, 3].pack("v2")).freeze
+
+    # Each paired with the cardinality its header claims.
+    [[corrupt_run, 33], [unsorted_array, 2]].each do |data, cardinality|
+      assert_raises(Roaring::DeserializationError) { Roaring::FrozenBitmap.view(data, verify: true) }
+
+      # Without verify, the containers' contents are only checked when the view is decoded.
+      frozen = Roaring::FrozenBitmap.view(data)
+      assert frozen.cardinality == cardinality
+      frozen.contains(5)
+      frozen.rank(5)
+      assert_raises(Roaring::DeserializationError) { frozen.to_bitmap }
+      assert_raises(Roaring::DeserializationError) { frozen | Roaring::Bitmap.new }
+    end
+  end
+
+  def test_survives_gc
+    data = Roaring::Bitmap.from_a([42]).serialize.freeze
+    frozen = Roaring::FrozenBitmap.view(data)
+    data = nil # rubocop:disable Lint/UselessAssignment
+    GC.start
+    GC.compact if GC.respond_to?(:compact)
+    assert frozen.to_a == [42]
+  end
+
+  def test_mmap
+    Tempfile.create(["bitmap", ".bin"]) do |file|
+      file.binmode
+      file.write(@bitmap.serialize)
+      file.close
+
+      mapped = Roaring::Bitmap.mmap(file.path)
+      assert_instance_of Roaring::FrozenBitmap, mapped
+      assert mapped.cardinality == @bitmap.cardinality
+      assert mapped.contains(105_000)
+      assert mapped.rank(70_000) == @bitmap.rank(70_000)
+      assert mapped.to_a == @bitmap.to_a
+      assert (mapped | Roaring::Bitmap.from_a([2])).cardinality == @bitmap.cardinality + 1
+      assert Roaring::Bitmap.mmap(Pathname(file.path)).cardinality == @bitmap.cardinality
+
+      assert Roaring::FrozenBitmap.mmap(file.path, verify: true).to_a == @bitmap.to_a
+      subclass = Class.new(Roaring::FrozenBitmap)
+      assert_instance_of subclass, subclass.mmap(file.path)
+      assert_instance_of subclass, subclass.view(@bitmap.serialize.freeze)
+    end
+  end
+
+  def test_shareable_across_ractors
+    skip "Ractor is not available" unless defined?(Ractor)
+
+    view = Ractor.make_shareable(@frozen)
+    assert Ractor.shareable?(view)
+
+    Tempfile.create(["bitmap", ".bin"]) do |file|
+      file.binmode
+      file.write(@bitmap.serialize)
+      file.close
+      mapped = Ractor.make_shareable(Roaring::Bitmap.mmap(file.path))
+
+      ractor = Ractor.new(view, mapped, file.path) do |rb, map, path|
+        [
+          rb.cardinality,
+          rb.contains(70_000),
+          map.to_a == rb.to_a,
+          Roaring::Bitmap.mmap(path).cardinality,
+          Roaring::FrozenBitmap.view(rb.to_bitmap.serialize.freeze).min
+        ]
+      end
+      result = ractor.respond_to?(:value) ? ractor.value : ractor.take
+      assert result == [@bitmap.cardinality, true, true, @bitmap.cardinality, 1]
+    end
+  end
+
+  def test_mmap_errors
+    assert_raises(IOError) { Roaring::Bitmap.mmap("/nonexistent/bitmap.bin") }
+
+    Tempfile.create("bitmap") do |file|
+      file.write("not a bitmap")
+      file.close
+      assert_raises(Roaring::DeserializationError) { Roaring::Bitmap.mmap(file.path) }
+      assert_raises(Roaring::DeserializationError) { Roaring::FrozenBitmap.mmap(file.path) }
+    end
+  end
+end